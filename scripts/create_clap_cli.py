@@ -129,6 +129,26 @@ for command, data in commands.items():
         }}]),
         ("subcommands", deepcopy(sub_args))
     ])
+    if command == 'list':
+        stuff['args'].extend([{
+            "limit": {
+                "long": "limit",
+                "help": "maximum number of items to list, all pages are fetched by default",
+                "takes_value": True,
+            }
+        }, {
+            "marker": {
+                "long": "marker",
+                "help": "start listing after the item with this id or name",
+                "takes_value": True,
+            }
+        }, {
+            "page-size": {
+                "long": "page-size",
+                "help": "number of items to request per page, every page is printed as soon as it arrives",
+                "takes_value": True,
+            }
+        }])
     if command == 'call':
         del stuff['subcommands']
         stuff['args'].extend([{
@@ -0,0 +1,308 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use chrono::prelude::*;
+use hmac::Hmac;
+use sha2::Sha256;
+
+use utils::user_cache_dir;
+
+// encrypted files start with MAGIC, the key source, the salt and the nonce
+const MAGIC: &[u8] = b"OSCC1";
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const PBKDF2_ROUNDS: u32 = 100_000;
+const EXTENSION: &str = "cache";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeySource {
+    Passphrase(String),
+    Keyring,
+}
+
+impl KeySource {
+    pub fn from_env() -> Option<KeySource> {
+        if let Ok(x) = std::env::var("OS_CLIENT_CACHE_PASSPHRASE") {
+            if x != "" {
+                return Some(KeySource::Passphrase(x));
+            }
+        }
+        match std::env::var("OS_CLIENT_CACHE_KEYRING") {
+            Ok(ref x) if (x == "1") | (x == "true") => Some(KeySource::Keyring),
+            _ => None,
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            KeySource::Passphrase(_) => b'p',
+            KeySource::Keyring => b'k',
+        }
+    }
+
+    fn key(&self, salt: &[u8]) -> Result<[u8; 32], Error> {
+        match self {
+            KeySource::Passphrase(x) => {
+                let mut key = [0u8; 32];
+                pbkdf2::pbkdf2::<Hmac<Sha256>>(x.as_bytes(), salt, PBKDF2_ROUNDS, &mut key)
+                    .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+                Ok(key)
+            }
+            KeySource::Keyring => keyring_key(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CacheEntry {
+    pub name: String,
+    pub path: String,
+    pub encrypted: bool,
+    pub modified: Option<String>,
+}
+
+pub fn cache_dir() -> Result<PathBuf, Error> {
+    if let Some(x) = std::env::var_os("OS_CLIENT_CACHE_DIR") {
+        return Ok(PathBuf::from(x));
+    }
+    match user_cache_dir() {
+        Some(x) => Ok(x.join("openstack-client")),
+        None => Err(Error::new(ErrorKind::NotFound, "no cache directory available")),
+    }
+}
+
+pub fn cache_path(name: &str) -> Result<PathBuf, Error> {
+    if name.is_empty() | !name.chars().all(|x| x.is_ascii_alphanumeric()) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("'{}' is not a valid cache name", name),
+        ));
+    }
+    Ok(cache_dir()?.join(format!("{}.{}", name, EXTENSION)))
+}
+
+pub fn read_cache(name: &str) -> Result<Vec<u8>, Error> {
+    let mut data = vec![];
+    fs::File::open(cache_path(name)?)?.read_to_end(&mut data)?;
+    decrypt(&data, KeySource::from_env().as_ref())
+}
+
+pub fn write_cache(name: &str, data: &[u8]) -> Result<(), Error> {
+    let path = cache_path(name)?;
+    create_private_dir(&cache_dir()?)?;
+    let data = match KeySource::from_env() {
+        Some(x) => encrypt(&x, data)?,
+        None => data.to_vec(),
+    };
+    create_private_file(&path)?.write_all(&data)?;
+    Ok(())
+}
+
+pub fn list_cache() -> Result<Vec<CacheEntry>, Error> {
+    let dir = cache_dir()?;
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut entries = vec![];
+    for item in fs::read_dir(dir)? {
+        let path = item?.path();
+        if path.extension().and_then(|x| x.to_str()) != Some(EXTENSION) {
+            continue;
+        }
+        let name = match path.file_stem().and_then(|x| x.to_str()) {
+            Some(x) => x.to_string(),
+            None => continue,
+        };
+        let mut data = vec![];
+        fs::File::open(&path)?.read_to_end(&mut data)?;
+        let modified = fs::metadata(&path)?
+            .modified()
+            .ok()
+            .map(|x| DateTime::<Utc>::from(x).to_rfc3339());
+        entries.push(CacheEntry {
+            name,
+            path: path.to_string_lossy().to_string(),
+            encrypted: is_encrypted(&data),
+            modified,
+        });
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+pub fn purge_cache(name: Option<&str>) -> Result<Vec<String>, Error> {
+    let mut removed = vec![];
+    for entry in list_cache()? {
+        if name.map(|x| x == entry.name).unwrap_or(true) {
+            fs::remove_file(&entry.path)?;
+            removed.push(entry.name);
+        }
+    }
+    Ok(removed)
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+pub fn encrypt(source: &KeySource, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut salt = [0u8; SALT_LENGTH];
+    let mut nonce = [0u8; NONCE_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+    let key = source.key(&salt)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    let encrypted = match cipher.encrypt(Nonce::from_slice(&nonce), data) {
+        Ok(x) => x,
+        Err(_e) => return Err(Error::new(ErrorKind::Other, "unable to encrypt cache")),
+    };
+    let mut output = MAGIC.to_vec();
+    output.push(source.tag());
+    output.extend_from_slice(&salt);
+    output.extend_from_slice(&nonce);
+    output.extend(encrypted);
+    Ok(output)
+}
+
+pub fn decrypt(data: &[u8], source: Option<&KeySource>) -> Result<Vec<u8>, Error> {
+    if !is_encrypted(data) {
+        return Ok(data.to_vec());
+    }
+    let salt_start = MAGIC.len() + 1;
+    let nonce_start = salt_start + SALT_LENGTH;
+    let data_start = nonce_start + NONCE_LENGTH;
+    if data.len() < data_start {
+        return Err(Error::new(ErrorKind::InvalidData, "invalid cache file"));
+    }
+    let source = match (data[MAGIC.len()], source) {
+        (b'p', Some(KeySource::Passphrase(x))) => KeySource::Passphrase(x.clone()),
+        (b'p', _) => {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "cache is encrypted with a passphrase, set OS_CLIENT_CACHE_PASSPHRASE",
+            ))
+        }
+        (b'k', _) => KeySource::Keyring,
+        _ => return Err(Error::new(ErrorKind::InvalidData, "invalid cache file")),
+    };
+    let key = source.key(&data[salt_start..nonce_start])?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    match cipher.decrypt(Nonce::from_slice(&data[nonce_start..data_start]), &data[data_start..]) {
+        Ok(x) => Ok(x),
+        Err(_e) => Err(Error::new(
+            ErrorKind::InvalidData,
+            "unable to decrypt cache, the key is probably wrong",
+        )),
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_key() -> Result<[u8; 32], Error> {
+    // a random key is created and stored in the keyring the first time
+    let to_error = |e: keyring::Error| Error::new(ErrorKind::Other, e.to_string());
+    let entry = keyring::Entry::new("openstack-client", "cache-key").map_err(to_error)?;
+    let encoded = match entry.get_password() {
+        Ok(x) => x,
+        Err(keyring::Error::NoEntry) => {
+            let mut key = [0u8; 32];
+            OsRng.fill_bytes(&mut key);
+            let encoded: String = key.iter().map(|x| format!("{:02x}", x)).collect();
+            entry.set_password(&encoded).map_err(to_error)?;
+            encoded
+        }
+        Err(e) => return Err(to_error(e)),
+    };
+    let mut key = [0u8; 32];
+    if encoded.len() != 64 {
+        return Err(Error::new(ErrorKind::InvalidData, "invalid cache key in keyring"));
+    }
+    for (i, x) in key.iter_mut().enumerate() {
+        *x = match u8::from_str_radix(&encoded[i * 2..i * 2 + 2], 16) {
+            Ok(y) => y,
+            Err(_e) => return Err(Error::new(ErrorKind::InvalidData, "invalid cache key in keyring")),
+        };
+    }
+    Ok(key)
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_key() -> Result<[u8; 32], Error> {
+    Err(Error::new(
+        ErrorKind::Other,
+        "openstack-client is built without keyring support",
+    ))
+}
+
+#[cfg(unix)]
+pub fn create_private_dir(dir: &Path) -> Result<(), Error> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)
+}
+
+#[cfg(not(unix))]
+pub fn create_private_dir(dir: &Path) -> Result<(), Error> {
+    fs::create_dir_all(dir)
+}
+
+#[cfg(unix)]
+pub fn create_private_file(path: &Path) -> Result<fs::File, Error> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // the mode is only used for new files
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+pub fn create_private_file(path: &Path) -> Result<fs::File, Error> {
+    fs::File::create(path)
+}
+
+#[test]
+fn test_encrypt_decrypt_with_passphrase() {
+    let source = KeySource::Passphrase("secret".to_string());
+    let encrypted = encrypt(&source, b"{\"token\": \"token\"}").unwrap();
+
+    assert!(is_encrypted(&encrypted));
+    assert!(!encrypted.windows(5).any(|x| x == b"token"));
+    assert_eq!(
+        decrypt(&encrypted, Some(&source)).unwrap(),
+        b"{\"token\": \"token\"}".to_vec()
+    );
+}
+
+#[test]
+fn test_decrypt_fails_with_wrong_or_missing_passphrase() {
+    let source = KeySource::Passphrase("secret".to_string());
+    let encrypted = encrypt(&source, b"{}").unwrap();
+    let other = KeySource::Passphrase("other".to_string());
+
+    assert_eq!(
+        decrypt(&encrypted, Some(&other)).unwrap_err().kind(),
+        ErrorKind::InvalidData
+    );
+    assert_eq!(
+        decrypt(&encrypted, None).unwrap_err().kind(),
+        ErrorKind::PermissionDenied
+    );
+}
+
+#[test]
+fn test_decrypt_passes_through_plain_cache() {
+    assert_eq!(decrypt(b"{}", None).unwrap(), b"{}".to_vec());
+}
+
+#[test]
+fn test_cache_path_rejects_invalid_names() {
+    assert!(cache_path("../../etc/passwd").is_err());
+    assert!(cache_path("").is_err());
+}
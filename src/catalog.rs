@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+use config::OpenstackInfoMap;
+#[cfg(test)]
+use config::ServiceOptions;
+use utils::add_slash;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct Endpoint {
+    pub id: String,
+    pub interface: String,
+    pub region: String,
+    pub region_id: String,
+    pub url: String,
+}
+
+impl Endpoint {
+    pub fn region(&self) -> &str {
+        // 'region' is deprecated in favour of 'region_id'
+        match self.region_id.as_ref() {
+            "" => &self.region,
+            x => x,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct Service {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub service_type: String,
+    pub endpoints: Vec<Endpoint>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct ServiceCatalog {
+    pub services: Vec<Service>,
+}
+
+impl ServiceCatalog {
+    pub fn from_token_response(data: &serde_json::Value) -> Self {
+        let services = match data["token"]["catalog"].as_array() {
+            Some(x) => x
+                .iter()
+                .filter_map(|y| serde_json::from_value(y.clone()).ok())
+                .collect(),
+            None => vec![],
+        };
+        ServiceCatalog { services }
+    }
+
+    pub fn find_endpoint(
+        &self,
+        service_type: &str,
+        interface: &str,
+        region: &str,
+        service_name: &str,
+    ) -> Option<&Endpoint> {
+        // an empty interface, region or service name matches everything
+        self.services
+            .iter()
+            .filter(|x| x.service_type == service_type)
+            .filter(|x| (service_name == "") | (x.name == service_name))
+            .flat_map(|x| x.endpoints.iter())
+            .filter(|x| (interface == "") | (x.interface == interface))
+            .find(|x| (region == "") | (x.region() == region))
+    }
+
+    pub fn endpoints(&self, config: &OpenstackInfoMap) -> HashMap<String, String> {
+        let mut endpoints = HashMap::new();
+        for service in self.services.iter() {
+            if endpoints.contains_key(&service.service_type) {
+                continue;
+            }
+            let options = config.service_options(&service.service_type);
+            let interface = options.interface.as_ref().unwrap_or(&config.interface);
+            let region = options.region_name.as_ref().unwrap_or(&config.region_name);
+            let service_name = options.service_name.clone().unwrap_or_default();
+            if let Some(x) = self.find_endpoint(&service.service_type, interface, region, &service_name) {
+                endpoints.insert(service.service_type.clone(), add_slash(&x.url));
+            }
+        }
+        endpoints
+    }
+}
+
+#[cfg(test)]
+fn test_catalog() -> ServiceCatalog {
+    ServiceCatalog::from_token_response(&json!({
+        "token": {
+            "catalog": [
+                {
+                    "id": "1",
+                    "name": "nova",
+                    "type": "compute",
+                    "endpoints": [
+                        {"id": "a", "interface": "public", "region_id": "ams", "region": "ams", "url": "https://compute.example.com:8774/v2.1"},
+                        {"id": "b", "interface": "internal", "region_id": "ams", "region": "ams", "url": "http://compute.internal/v2.1"},
+                        {"id": "c", "interface": "public", "region_id": "rtm", "region": "rtm", "url": "https://rtm.example.com/v2.1"}
+                    ]
+                },
+                {
+                    "id": "2",
+                    "name": "cinder",
+                    "type": "volumev3",
+                    "endpoints": [
+                        {"id": "d", "interface": "public", "region_id": "ams", "region": "ams", "url": "https://volume.example.com/v3"}
+                    ]
+                },
+                {
+                    "id": "3",
+                    "name": "cinder-legacy",
+                    "type": "volumev3",
+                    "endpoints": [
+                        {"id": "e", "interface": "public", "region_id": "ams", "region": "ams", "url": "https://legacy.example.com/v3"}
+                    ]
+                }
+            ]
+        }
+    }))
+}
+
+#[test]
+fn test_catalog_selects_interface_and_region() {
+    let catalog = test_catalog();
+    let mut config = OpenstackInfoMap::default();
+    config.region_name = "ams".to_string();
+
+    let endpoints = catalog.endpoints(&config);
+    assert_eq!(endpoints["compute"], "https://compute.example.com:8774/v2.1/");
+    assert_eq!(endpoints["volumev3"], "https://volume.example.com/v3/");
+
+    config.interface = "internal".to_string();
+    let endpoints = catalog.endpoints(&config);
+    assert_eq!(endpoints["compute"], "http://compute.internal/v2.1/");
+    assert!(endpoints.get("volumev3").is_none());
+}
+
+#[test]
+fn test_catalog_honors_service_overrides() {
+    let catalog = test_catalog();
+    let mut config = OpenstackInfoMap::default();
+    config.region_name = "ams".to_string();
+    config.services.insert(
+        "compute".to_string(),
+        ServiceOptions {
+            region_name: Some("rtm".to_string()),
+            ..Default::default()
+        },
+    );
+    config.services.insert(
+        "volumev3".to_string(),
+        ServiceOptions {
+            service_name: Some("cinder-legacy".to_string()),
+            ..Default::default()
+        },
+    );
+
+    let endpoints = catalog.endpoints(&config);
+    assert_eq!(endpoints["compute"], "https://rtm.example.com/v2.1/");
+    assert_eq!(endpoints["volumev3"], "https://legacy.example.com/v3/");
+}
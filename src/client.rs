@@ -2,16 +2,25 @@
 use std::collections::HashMap;
 use std::io::{stdout, Read, Write};
 use std::fs::File;
+use std::thread;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use chrono::prelude::*;
+use rand::Rng;
 
 use curl::easy::{Easy, List};
 use serde_json::Value as JSONValue;
 use error::OpenstackError;
+use headers::HeaderMap;
+use trace;
+use recording::{self, Exchange, RecordedRequest, Replay};
 use memmap::MmapOptions;
 use indicatif::{ProgressBar, ProgressStyle};
 
 use objectstore::{create_file, download_from_object_store, open_file, upload_to_object_store, upload_to_object_store_dynamic_large_objects};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Client {
     #[serde(skip, default = "Easy::new")]
     pub handle: Easy,
@@ -19,10 +28,189 @@ pub struct Client {
     pub url: Option<String>,
     pub method: Option<String>,
     pub json: JSONValue,
+    #[serde(skip)]
+    pub retry: RetryPolicy,
+    #[serde(skip)]
+    pub options: ClientOptions,
+    #[serde(skip)]
+    replay: Option<Replay>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientOptions {
+    pub cacert: Option<String>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    // skips verification of the server certificate, 'verify: false' in clouds.yaml
+    pub insecure: bool,
+    pub connect_timeout: Duration,
+    // the maximum duration of an api call, object store transfers only time out when they stall
+    pub timeout: Option<Duration>,
+    // http, https or socks proxy, curl falls back to the usual proxy env vars
+    pub proxy: Option<String>,
+    pub no_proxy: Option<String>,
+    pub user_agent: String,
+    // logs every request and response to stderr, without secrets
+    pub debug_http: bool,
+    // json-lines files to save the api calls in, or to answer them from instead of the network
+    pub record: Option<String>,
+    pub replay: Option<String>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            cacert: None,
+            cert: None,
+            key: None,
+            insecure: false,
+            connect_timeout: Duration::from_secs(30),
+            timeout: Some(Duration::from_secs(300)),
+            proxy: None,
+            no_proxy: None,
+            user_agent: default_user_agent(),
+            debug_http: false,
+            record: None,
+            replay: None,
+        }
+    }
+}
+
+pub fn default_user_agent() -> String {
+    format!("openstack-client/{}", env!("CARGO_PKG_VERSION"))
+}
+
+impl ClientOptions {
+    pub fn new_handle(&self) -> Result<Easy, curl::Error> {
+        let mut handle = Easy::new();
+        self.configure_handle(&mut handle)?;
+        Ok(handle)
+    }
+
+    pub fn new_transfer_handle(&self) -> Result<Easy, curl::Error> {
+        let mut handle = self.new_handle()?;
+        if let Some(x) = self.timeout {
+            // zero turns the total timeout off, less than a byte per second counts as stalled
+            handle.timeout(Duration::from_secs(0))?;
+            handle.low_speed_limit(1)?;
+            handle.low_speed_time(x)?;
+        }
+        Ok(handle)
+    }
+
+    pub fn configure_handle(&self, handle: &mut Easy) -> Result<(), curl::Error> {
+        handle.useragent(&self.user_agent)?;
+        handle.connect_timeout(self.connect_timeout)?;
+        if let Some(x) = self.timeout {
+            handle.timeout(x)?;
+        }
+        if let Some(x) = &self.proxy {
+            handle.proxy(x)?;
+        }
+        if let Some(x) = &self.no_proxy {
+            handle.noproxy(x)?;
+        }
+        if let Some(x) = &self.cacert {
+            handle.cainfo(x)?;
+        }
+        if let Some(x) = &self.cert {
+            handle.ssl_cert(x)?;
+        }
+        if let Some(x) = &self.key {
+            handle.ssl_key(x)?;
+        }
+        if self.insecure {
+            handle.ssl_verify_peer(false)?;
+            handle.ssl_verify_host(false)?;
+        }
+        if self.debug_http {
+            handle.verbose(true)?;
+            handle.debug_function(trace::debug_function)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    // retries after 429, 502, 503 and 504 responses
+    pub status_retries: u32,
+    // retries after connection failures
+    pub connect_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            status_retries: 2,
+            connect_retries: 2,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn is_idempotent(method: &str) -> bool {
+        match method.to_uppercase().as_ref() {
+            "GET" | "HEAD" | "OPTIONS" | "PUT" | "DELETE" | "TRACE" => true,
+            _ => false,
+        }
+    }
+
+    fn may_retry(&self, method: &str) -> bool {
+        self.retry_non_idempotent | Self::is_idempotent(method)
+    }
+
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        // exponential backoff with jitter, somewhere between half and the full delay
+        let factor = 2u32.saturating_pow(attempt.min(16));
+        let delay = self.base_delay.checked_mul(factor).unwrap_or(self.max_delay).min(self.max_delay);
+        let millis = delay.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(millis / 2, millis + 1))
+    }
+
+    pub fn response_delay(&self, method: &str, response: &Response, attempt: u32) -> Option<Duration> {
+        match response.status() {
+            429 | 502 | 503 | 504 => (),
+            _ => return None,
+        };
+        if (attempt >= self.status_retries) | !self.may_retry(method) {
+            return None;
+        }
+        match response.retry_after() {
+            // waiting longer than the maximum delay is not worth it
+            Some(x) if x > self.max_delay => None,
+            Some(x) => Some(x),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+
+    pub fn error_delay(&self, method: &str, err: &curl::Error, attempt: u32) -> Option<Duration> {
+        if attempt >= self.connect_retries {
+            return None;
+        }
+        // without a connection the request never reached the server, so any method can be retried
+        if err.is_couldnt_connect() {
+            return Some(self.backoff(attempt));
+        }
+        let is_transient = err.is_operation_timedout()
+            | err.is_send_error()
+            | err.is_recv_error()
+            | err.is_got_nothing();
+        if is_transient && self.may_retry(method) {
+            return Some(self.backoff(attempt));
+        }
+        None
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Response ( pub JSONValue, pub u32, pub Vec<String> );
+pub struct Response ( pub JSONValue, pub u32, pub HeaderMap );
 
 
 impl Response{
@@ -34,36 +222,66 @@ impl Response{
         self.0.clone()
     }
 
-    pub fn headers(&self) -> Vec<String>{
-        self.2.clone()
+    pub fn headers(&self) -> &HeaderMap{
+        &self.2
     }
 
-    pub fn parsed_headers(&self) -> HashMap<String, String>{
-        let mut headers = HashMap::new();
-        for item in self.headers(){
-            let new_item = item.clone();
-            let mut split = new_item.split(':');
-            let key = match split.next(){
-                Some(x) => x.trim(),
-                None => continue
-            };
-            let value = match split.next(){
-                Some(x) => x.trim(),
-                None => continue
-            };
-            headers.insert(key.to_string(), value.to_string());
+    pub fn retry_after(&self) -> Option<Duration>{
+        // either a number of seconds or a http date
+        let value = *self.headers().get_all("retry-after").last()?;
+        if let Ok(x) = value.parse(){
+            return Some(Duration::from_secs(x));
         }
-        headers
+        let date = DateTime::parse_from_rfc2822(value).ok()?;
+        let seconds = (date.with_timezone(&Utc) - Utc::now()).num_seconds();
+        Some(Duration::from_secs(seconds.max(0) as u64))
     }
 
     pub fn is_success(&self) -> bool{
         !((self.1 / 100 == 4) | (self.1 / 100 == 5))
     }
+
+    pub fn request_id(&self) -> Option<String>{
+        // nova and cinder use x-compute-request-id, swift x-trans-id
+        ["x-openstack-request-id", "x-compute-request-id", "x-trans-id"]
+            .iter()
+            .filter_map(|x| self.headers().get(x))
+            .map(|x| x.to_string())
+            .next()
+    }
+
+    pub fn with_metadata(&self, body: JSONValue) -> JSONValue{
+        json!({
+            "status": self.status(),
+            "request_id": self.request_id(),
+            "headers": self.headers().to_json(),
+            "body": body
+        })
+    }
 }
 
 impl Default for Response{
     fn default() -> Self{
-        Response{0: JSONValue::Null, 1: 0, 2: vec![]}
+        Response{0: JSONValue::Null, 1: 0, 2: HeaderMap::new()}
+    }
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // the token is in the headers and passwords can be in the body
+        let headers: HashMap<&String, String> = self
+            .headers
+            .iter()
+            .map(|(k, v)| (k, trace::redact_header(k, v)))
+            .collect();
+        f.debug_struct("Client")
+            .field("headers", &headers)
+            .field("url", &self.url)
+            .field("method", &self.method)
+            .field("json", &trace::redact_json(&self.json))
+            .field("retry", &self.retry)
+            .field("options", &self.options)
+            .finish()
     }
 }
 
@@ -74,7 +292,9 @@ impl Client {
         let url = None;
         let method = None;
         let json = JSONValue::Null;
-        Client { handle, headers, url, method, json }
+        let retry = RetryPolicy::default();
+        let options = ClientOptions::default();
+        Client { handle, headers, url, method, json, retry, options, replay: None }
     }
 
     pub fn set_token(&mut self, token: &str) {
@@ -148,8 +368,72 @@ impl Client {
         url: &str,
         json: JSONValue,
     ) -> Result<Response, OpenstackError> {
+        let mut status_attempts = 0;
+        let mut connect_attempts = 0;
+        let body = match method.to_lowercase().as_ref() {
+            "post" | "put" | "patch" => Some(&json),
+            _ => None
+        };
+        loop {
+            let started = Instant::now();
+            if self.options.debug_http {
+                trace::log_request(method, url, &self.headers, body);
+            }
+            let outcome = match self.options.replay.clone() {
+                Some(x) => Ok(self.replay_once(&x, method, url, body)?),
+                None => self.request_once(method, url, json.clone())
+            };
+            if let (Some(x), Ok(y)) = (&self.options.record, &outcome) {
+                recording::record(x, &Exchange::new(RecordedRequest::new(method, url, body), y))?;
+            }
+            if self.options.debug_http {
+                trace::log_finished(method, url, outcome.as_ref().ok().map(|x| x.status()), started.elapsed());
+            }
+            let delay = match &outcome {
+                Ok(x) => {
+                    status_attempts += 1;
+                    self.retry.response_delay(method, x, status_attempts - 1)
+                }
+                Err(e) => {
+                    connect_attempts += 1;
+                    self.retry.error_delay(method, e, connect_attempts - 1)
+                }
+            };
+            match delay {
+                Some(x) => {
+                    debug!("retrying {} {} in {:?}", method, url, x);
+                    if self.options.replay.is_none() {
+                        thread::sleep(x);
+                    }
+                }
+                None => return Ok(outcome?),
+            }
+        }
+    }
+
+    fn replay_once(
+        &mut self,
+        path: &str,
+        method: &str,
+        url: &str,
+        body: Option<&JSONValue>,
+    ) -> Result<Response, OpenstackError> {
+        if self.replay.is_none() {
+            self.replay = Some(Replay::from_file(path)?);
+        }
+        let replay = self.replay.as_mut().expect("the recording is loaded above");
+        replay.respond(&RecordedRequest::new(method, url, body))
+    }
+
+    fn request_once(
+        &mut self,
+        method: &str,
+        url: &str,
+        json: JSONValue,
+    ) -> Result<Response, curl::Error> {
         let mut data = Vec::new();
 
+        self.options.configure_handle(&mut self.handle)?;
         self.handle.url(url)?;
         self.handle.custom_request(method)?;
 
@@ -169,7 +453,7 @@ impl Client {
         }
         self.handle.http_headers(local_headers)?;
 
-        let mut remote_headers = vec![];
+        let mut remote_headers = HeaderMap::new();
 
         {
             let body = json.to_string();
@@ -179,7 +463,7 @@ impl Client {
                 Ok(new_data.len())
             })?;
             transfer.header_function(|header| {
-                remote_headers.push(String::from_utf8(header.to_vec()).unwrap_or(String::from(" : ")));
+                remote_headers.push_line(header);
                 true
             })?;
 
@@ -225,7 +509,7 @@ impl Client {
     fn open_file_ect(&mut self, filename: &str) -> Result<(File, String), OpenstackError> {
         let token = match self.get_token(){
             Some(x) => x,
-            None => return Err(OpenstackError::new("token is not set"))
+            None => return Err(OpenstackError::auth("token is not set"))
         };
 
         let mut file = open_file(filename)?;
@@ -235,30 +519,81 @@ impl Client {
 
     pub fn upload_to_object_store(&mut self, filename: &str, objectstore_url: &str) -> Result<Response, OpenstackError> {
         let (mut file, token) = self.open_file_ect(filename)?;
-        upload_to_object_store(&mut file, objectstore_url, &token)
+        upload_to_object_store(&mut file, objectstore_url, &token, &self.options)
     }
 
     pub fn upload_to_object_store_large(&mut self, filename: &str, objectstore_url: &str, container: &str, name: &str) -> Result<Response, OpenstackError> {
         let (mut file, token) = self.open_file_ect(filename)?;
-        upload_to_object_store_dynamic_large_objects(&mut file, name, container, objectstore_url, &token, 20, 0)
+        upload_to_object_store_dynamic_large_objects(&mut file, name, container, objectstore_url, &token, 20, 0, &self.options)
     }
 
     pub fn upload_to_object_store_large_with_parts(&mut self, filename: &str, objectstore_url: &str, container: &str, name: &str, parts: usize) -> Result<Response, OpenstackError> {
         let (mut file, token) = self.open_file_ect(filename)?;
-        upload_to_object_store_dynamic_large_objects(&mut file, name, container, objectstore_url, &token, parts, 0)
+        upload_to_object_store_dynamic_large_objects(&mut file, name, container, objectstore_url, &token, parts, 0, &self.options)
     }
 
     pub fn upload_to_object_store_large_skip_parts(&mut self, filename: &str, objectstore_url: &str, container: &str, name: &str, parts: usize, skip_first: usize) -> Result<Response, OpenstackError> {
         let (mut file, token) = self.open_file_ect(filename)?;
-        upload_to_object_store_dynamic_large_objects(&mut file, name, container, objectstore_url, &token, parts, skip_first)
+        upload_to_object_store_dynamic_large_objects(&mut file, name, container, objectstore_url, &token, parts, skip_first, &self.options)
     }
 
     pub fn download_from_object_store(&mut self, outfile: &str, objectstore_url: &str) -> Result<Response, OpenstackError> {
         let token = match self.get_token(){
-            Some(x) => x,
-            None => return Err(OpenstackError::new("token is not set"))
+            Some(x) => x.clone(),
+            None => return Err(OpenstackError::auth("token is not set"))
         };
         let mut file = create_file(outfile)?;
-        download_from_object_store(&mut file, objectstore_url, token)
+        download_from_object_store(&mut file, objectstore_url, &token, &self.options)
     }
 }
+
+#[test]
+fn test_retry_policy_only_retries_transient_statuses() {
+    let policy = RetryPolicy {
+        base_delay: Duration::from_millis(100),
+        ..Default::default()
+    };
+    let unavailable = Response(JSONValue::Null, 503, HeaderMap::new());
+    let not_found = Response(JSONValue::Null, 404, HeaderMap::new());
+
+    let delay = policy.response_delay("GET", &unavailable, 0).unwrap();
+    assert!((delay >= Duration::from_millis(50)) & (delay <= Duration::from_millis(100)));
+    let delay = policy.response_delay("GET", &unavailable, 1).unwrap();
+    assert!((delay >= Duration::from_millis(100)) & (delay <= Duration::from_millis(200)));
+    assert_eq!(policy.response_delay("GET", &unavailable, 2), None);
+    assert_eq!(policy.response_delay("POST", &unavailable, 0), None);
+    assert_eq!(policy.response_delay("GET", &not_found, 0), None);
+}
+
+#[test]
+fn test_retry_policy_honors_retry_after() {
+    let policy = RetryPolicy::default();
+    let throttled = Response(JSONValue::Null, 429, HeaderMap::from_lines(&["Retry-After: 3\r\n"]));
+    assert_eq!(policy.response_delay("GET", &throttled, 0), Some(Duration::from_secs(3)));
+
+    let too_long = Response(JSONValue::Null, 429, HeaderMap::from_lines(&["retry-after: 3600\r\n"]));
+    assert_eq!(policy.response_delay("GET", &too_long, 0), None);
+
+    let date = (Utc::now() - chrono::Duration::seconds(10)).to_rfc2822();
+    let in_the_past = Response(JSONValue::Null, 503, HeaderMap::from_lines(&[&format!("Retry-After: {}\r\n", date)]));
+    assert_eq!(in_the_past.retry_after(), Some(Duration::from_secs(0)));
+}
+
+#[test]
+fn test_response_with_metadata() {
+    let response = Response(
+        JSONValue::Null,
+        202,
+        HeaderMap::from_lines(&["HTTP/1.1 202 Accepted\r\n", "X-Compute-Request-Id: req-1\r\n"]),
+    );
+    assert_eq!(response.request_id(), Some("req-1".to_string()));
+    assert_eq!(
+        response.with_metadata(json!({"id": "1"})),
+        json!({
+            "status": 202,
+            "request_id": "req-1",
+            "headers": {"x-compute-request-id": "req-1"},
+            "body": {"id": "1"}
+        })
+    );
+}
@@ -1,15 +1,26 @@
 use std::collections::HashMap;
 use std::collections::hash_map::DefaultHasher;
-use std::io::{Error, ErrorKind, Read, Write};
+use std::io::{Error, Read, Write};
 use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
 use sha2::Digest;
 
-use client::{Client, Response};
+use chrono::prelude::*;
+use chrono::Duration;
+
+use cache;
+use catalog::ServiceCatalog;
+use discovery::ServiceVersion;
+use client::{Client, ClientOptions, Response, RetryPolicy};
+use error::{HttpError, OpenstackError};
 use utils::{
     add_slash, get_first_value_from_hashmap_with_vec, hashmap_with_vec_to_json,
-    make_hashmaps_from_dot_notation, read_yaml, remove_slash_start,
+    make_hashmaps_from_dot_notation, merge_yaml, read_yaml, remove_slash_start, user_config_dir,
 };
-use traits::SerdeList;
+use traits::{SecretStore, SerdeList};
+
+// used when neither clouds.yaml nor OS_INTERFACE sets an interface
+const DEFAULT_INTERFACE: &str = "public";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OpenstackTokenizer {
@@ -17,8 +28,12 @@ pub struct OpenstackTokenizer {
     pub token: Option<String>,
     pub token_expiry: Option<String>,
     pub endpoints: Option<HashMap<String, String>>,
+    pub catalog: Option<ServiceCatalog>,
     pub domain_id: Option<String>,
     pub user_id: Option<String>,
+    // discovered api versions per service type
+    #[serde(default)]
+    pub versions: HashMap<String, ServiceVersion>,
 }
 
 impl OpenstackTokenizer {
@@ -28,8 +43,10 @@ impl OpenstackTokenizer {
             token: None,
             token_expiry: None,
             endpoints: None,
+            catalog: None,
             domain_id: None,
             user_id: None,
+            versions: HashMap::new(),
         }
     }
 
@@ -45,14 +62,37 @@ impl OpenstackTokenizer {
     // - from reader
 
     pub fn from_cache(config: &OpenstackInfoMap) -> Result<Self, Error> {
-        let dir = Self::get_tmp_cache_location(config);
-        debug!("using cache from {:?}", dir);
-        let file = std::fs::File::open(dir)?;
-        let reader = std::io::BufReader::new(file);
-        let obj = Self::from_reader(reader)?;
+        let name = config.auth.create_hash();
+        debug!("using cache from {:?}", cache::cache_path(&name)?);
+        let data = cache::read_cache(&name)?;
+        let mut obj = Self::from_reader(&data[..])?;
+        // secrets are not cached, so use the current config for refreshing the token
+        obj.config = config.clone();
+        if let Some(x) = &obj.catalog {
+            obj.endpoints = Some(x.endpoints(&obj.config));
+        }
         Ok(obj)
     }
 
+    pub fn token_expires_within(&self, margin: Duration) -> bool {
+        // an unknown expiry is handled when the api returns a 401
+        let expiry = match &self.token_expiry {
+            Some(x) => x,
+            None => return false,
+        };
+        match DateTime::parse_from_rfc3339(expiry) {
+            Ok(x) => x.with_timezone(&Utc) - margin <= Utc::now(),
+            Err(_e) => true,
+        }
+    }
+
+    pub fn needs_refresh(&self) -> bool {
+        if self.config.auth.uses_no_auth() {
+            return false;
+        }
+        self.token.is_none() | self.token_expires_within(self.config.token_expiry_margin())
+    }
+
     pub fn from_reader<R>(reader: R) -> Result<Self, Error>
         where R: Read
     {
@@ -64,10 +104,11 @@ impl OpenstackTokenizer {
     // - to writer
 
     pub fn to_cache(&self) -> Result<(), Error>{
-        let dir = Self::get_tmp_cache_location(&self.config);
-        let file = std::fs::File::create(dir)?;
-        let writer = std::io::BufWriter::new(file);
-        self.to_writer(writer)?;
+        let mut data = vec![];
+        self.to_writer(&mut data)?;
+        cache::write_cache(&self.config.auth.create_hash(), &data)?;
+        // older versions used a world readable file in the temp dir
+        let _ = std::fs::remove_file(Self::get_legacy_cache_location(&self.config));
         Ok(())
     }
 
@@ -78,36 +119,56 @@ impl OpenstackTokenizer {
         Ok(())
     }
 
-    fn get_tmp_cache_location(config: &OpenstackInfoMap) -> std::path::PathBuf {
+    fn get_legacy_cache_location(config: &OpenstackInfoMap) -> std::path::PathBuf {
         let mut dir = std::env::temp_dir();
         let filename_hash = format!("openstack-client-{}", config.auth.create_hash());
         dir.push(filename_hash);
         dir
     }
 
-    pub fn refresh_token(&mut self) -> Result<(), Error> {
+    pub fn refresh_token(&mut self) -> Result<(), OpenstackError> {
+        if self.config.auth.uses_no_auth() {
+            debug!("auth_type is none, using endpoint overrides only");
+            self.token = None;
+            self.endpoints = Some(HashMap::new());
+            return Ok(());
+        }
         // let body = create_token_body(&self.config.auth);
-        let body = self.config.auth.pick_token_body();
+        let methods = self.config.auth.auth_methods();
+        self.config.add_passcode_if_needed(&methods)?;
+        let body = self.config.auth.pick_token_body()?;
 
         let auth_url = match url::Url::parse(&add_slash(&self.config.auth.auth_url)) {
             Ok(x) => x.join("auth/tokens").unwrap(),
-            Err(_e) => return Err(Error::new(ErrorKind::InvalidData, "Not a valid auth_url")),
+            Err(_e) => return Err(OpenstackError::config("Not a valid auth_url")),
         };
         let mut client = Client::new();
-        let mut response = match client.post(auth_url.as_str(), body) {
-            Ok(x) => x,
-            Err(e) => return Err(Error::new(ErrorKind::Other, e.to_string())),
-        };
+        client.retry = self.config.retry_policy();
+        client.options = self.config.client_options();
+        let mut response = client.post(auth_url.as_str(), body)?;
+
+        // multi factor auth rules, send the missing methods together with the receipt
+        if response.status() == 401 {
+            if let Some(receipt) = response.headers().get("openstack-auth-receipt").map(|x| x.to_string()) {
+                let missing_methods = missing_auth_methods(&response.response());
+                debug!("auth receipt received, missing methods {:?}", missing_methods);
+                self.config.add_passcode_if_needed(&missing_methods)?;
+                let body = create_token_body_for_methods(&self.config.auth, &missing_methods);
+                client.set_header("Openstack-Auth-Receipt", &receipt);
+                response = client.post(auth_url.as_str(), body)?;
+            }
+        }
 
         let response_json = response.response();
 
         if !response.is_success() {
-            return Err(Error::new(
-                ErrorKind::Other,
-                serde_json::to_string(&response_json).unwrap(),
-            ));
+            let error = HttpError::from_response(&response);
+            return match error.status {
+                401 | 403 => Err(OpenstackError::auth(&error.to_string())),
+                _ => Err(error.into()),
+            };
         }
-        self.parse_token_reponse(&response);
+        self.parse_token_reponse(&response)?;
         self.parse_identity_reponse(&response_json);
         self.parse_token_expiry_reponse(&response_json);
         self.parse_token_user_domain_reponse(&response_json);
@@ -115,60 +176,19 @@ impl OpenstackTokenizer {
     }
 
     fn parse_identity_reponse(&mut self, data: &serde_json::Value) {
-        let mut endpoints: HashMap<String, String> = HashMap::new();
-        // println!("{}", serde_json::to_string_pretty(data).unwrap());
-
-        match data["token"]["catalog"].as_array() {
-            Some(catalog) => {
-                'outer: for service in catalog.iter() {
-                    let the_type: String = match service["type"].as_str() {
-                        Some(x) => x.into(),
-                        None => continue,
-                    };
-                    match service["endpoints"].as_array() {
-                        Some(data_endpoints) => {
-                            'inner: for endpoint in data_endpoints {
-                                let inferface_end: String =
-                                    endpoint["inferface"].as_str().unwrap_or("public").into();
-                                let region_name_end: String = match endpoint["region_id"].as_str() {
-                                    Some(x) => x.into(),
-                                    None => continue 'inner,
-                                };
-                                let url_end: String = match endpoint["url"].as_str() {
-                                    Some(x) => x.into(),
-                                    None => continue 'inner,
-                                };
-                                if self.config.only_use_public_endpoints {
-                                    match url::Url::parse(&url_end).unwrap().port() {
-                                        Some(_x) => continue 'inner,
-                                        _ => (),
-                                    };
-                                }
-                                if (self.config.region_name == region_name_end)
-                                    & (self.config.interface == inferface_end)
-                                {
-                                    endpoints.insert(the_type.clone(), add_slash(&url_end.clone()));
-                                }
-                            }
-                        }
-                        None => (),
-                    }
-                }
-            }
-            None => (),
-        }
-        self.endpoints = Some(endpoints);
+        let catalog = ServiceCatalog::from_token_response(data);
+        self.endpoints = Some(catalog.endpoints(&self.config));
+        self.catalog = Some(catalog);
     }
 
-    fn parse_token_reponse(&mut self, data: &Response) {
-        let headers = data.clone().parsed_headers();
-        let os_token = match headers.get("x-subject-token") {
+    fn parse_token_reponse(&mut self, data: &Response) -> Result<(), OpenstackError> {
+        // a proxy or a stale recording can answer 2xx without a token
+        let os_token = match data.headers().get("x-subject-token") {
             Some(x) => x,
-            None => headers
-                .get("X-Subject-Token")
-                .expect("Expected an auth token"),
+            None => return Err(OpenstackError::auth("the identity service did not return a token")),
         };
         self.token = Some(os_token.to_string());
+        Ok(())
     }
 
     fn parse_token_expiry_reponse(&mut self, data: &serde_json::Value) {
@@ -192,7 +212,7 @@ impl OpenstackTokenizer {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 #[serde(default, rename_all = "snake_case")]
 pub struct OpenstackInfoMap {
     pub cloud_name: String,
@@ -206,7 +226,75 @@ pub struct OpenstackInfoMap {
     pub region_name: String,
     pub interface: String,
     pub auth: Auth,
-    pub only_use_public_endpoints: bool,
+    // in seconds, the token is refreshed when it expires within this margin
+    pub token_expiry_margin: Option<i64>,
+    pub services: HashMap<String, ServiceOptions>,
+    // retries after 429, 502, 503 and 504 responses
+    pub api_retry_count: Option<u32>,
+    // retries after connection failures
+    pub connect_retries: Option<u32>,
+    pub cacert: Option<String>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub verify: Option<bool>,
+    // in seconds
+    pub connect_timeout: Option<u64>,
+    pub api_timeout: Option<u64>,
+    pub proxy: Option<String>,
+    pub no_proxy: Option<String>,
+    // put in front of the default user agent
+    pub user_agent: Option<String>,
+    // only set with --debug-http
+    #[serde(skip)]
+    pub debug_http: bool,
+    // only set with --record and --replay
+    #[serde(skip)]
+    pub record: Option<String>,
+    #[serde(skip)]
+    pub replay: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+#[serde(default)]
+pub struct ServiceOptions {
+    pub interface: Option<String>,
+    pub region_name: Option<String>,
+    pub service_name: Option<String>,
+    pub endpoint_override: Option<String>,
+    pub api_version: Option<String>,
+}
+
+impl ServiceOptions {
+    pub fn apply(&mut self, other: &Self) -> &mut Self {
+        if other.interface.is_some() {
+            self.interface = other.interface.clone();
+        }
+        if other.region_name.is_some() {
+            self.region_name = other.region_name.clone();
+        }
+        if other.service_name.is_some() {
+            self.service_name = other.service_name.clone();
+        }
+        if other.endpoint_override.is_some() {
+            self.endpoint_override = other.endpoint_override.clone();
+        }
+        if other.api_version.is_some() {
+            self.api_version = other.api_version.clone();
+        }
+        self
+    }
+
+    pub fn set(&mut self, option: &str, value: String) -> bool {
+        match option {
+            "interface" => self.interface = Some(value),
+            "region_name" => self.region_name = Some(value),
+            "service_name" => self.service_name = Some(value),
+            "endpoint_override" => self.endpoint_override = Some(value),
+            "api_version" => self.api_version = Some(value),
+            _ => return false,
+        };
+        true
+    }
 }
 
 impl OpenstackInfoMap {
@@ -223,12 +311,6 @@ impl OpenstackInfoMap {
         auth: Auth,
     ) -> OpenstackInfoMap {
         // let ps: secstr::SecUtf8 = secstr::SecUtf8::from(password);
-        let only_use_public_endpoints;
-        if interface == "public" {
-            only_use_public_endpoints = true
-        } else {
-            only_use_public_endpoints = false
-        }
         OpenstackInfoMap {
             cloud_name,
             // auth_url,
@@ -240,7 +322,22 @@ impl OpenstackInfoMap {
             region_name,
             interface,
             auth,
-            only_use_public_endpoints,
+            token_expiry_margin: None,
+            services: HashMap::new(),
+            api_retry_count: None,
+            connect_retries: None,
+            cacert: None,
+            cert: None,
+            key: None,
+            verify: None,
+            connect_timeout: None,
+            api_timeout: None,
+            proxy: None,
+            no_proxy: None,
+            user_agent: None,
+            debug_http: false,
+            record: None,
+            replay: None,
         }
     }
 
@@ -248,24 +345,46 @@ impl OpenstackInfoMap {
 
     // }
 
-    pub fn from_clouds_yaml(region: String) -> Result<OpenstackInfoMap, Error> {
-        OpenstackInfoMap::from_yaml("clouds.yaml".to_string(), region)
-        // current directory
-        // ~/.config/openstack
-        // /etc/openstack
+    pub fn from_clouds_yaml(region: String) -> Result<OpenstackInfoMap, OpenstackError> {
+        let found = match find_config_file(Some("OS_CLIENT_CONFIG_FILE"), "clouds.yaml") {
+            Some(x) => OpenstackInfoMap::from_yaml(x.to_string_lossy().to_string(), region.clone()),
+            None => Err(OpenstackError::config("clouds.yaml not found")),
+        };
+        // errors name the cloud that was asked for
+        found.map_err(|e| match region.as_str() {
+            "" => e,
+            x => OpenstackError::config(&format!("cloud '{}': {}", x, e)),
+        })
     }
 
-    pub fn from_yaml(location: String, region: String) -> Result<OpenstackInfoMap, Error> {
-        let value = match read_yaml(location) {
-            Ok(x) => x,
-            Err(e) => return Err(e),
+    pub fn from_yaml(location: String, region: String) -> Result<OpenstackInfoMap, OpenstackError> {
+        debug!("using clouds.yaml from {:?}", location);
+        let read = |x: String| read_yaml(x).map_err(|e| OpenstackError::config(&e.to_string()));
+        let mut value = read(location)?;
+        if let Some(secure_location) = find_config_file(Some("OS_CLIENT_SECURE_FILE"), "secure.yaml") {
+            debug!("merging secure.yaml from {:?}", secure_location);
+            let secure_value = read(secure_location.to_string_lossy().to_string())?;
+            merge_yaml(&mut value, &secure_value);
+        }
+        let vendors = match find_config_file(None, "clouds-public.yaml") {
+            Some(x) => read(x.to_string_lossy().to_string())?,
+            None => serde_yaml::Value::Null,
         };
-        Self::parse_clouds_yaml(value, region)
+        Self::parse_clouds_yaml_with_vendors(value, vendors, region)
     }
+
     fn parse_clouds_yaml(
         value: serde_yaml::Value,
         region: String,
-    ) -> Result<OpenstackInfoMap, Error> {
+    ) -> Result<OpenstackInfoMap, OpenstackError> {
+        Self::parse_clouds_yaml_with_vendors(value, serde_yaml::Value::Null, region)
+    }
+
+    fn parse_clouds_yaml_with_vendors(
+        value: serde_yaml::Value,
+        vendors: serde_yaml::Value,
+        region: String,
+    ) -> Result<OpenstackInfoMap, OpenstackError> {
         let mut region_copy = region.clone();
         if &region_copy == "" {
             let lengt = match &value["clouds"] {
@@ -273,10 +392,7 @@ impl OpenstackInfoMap {
                 _ => 0,
             };
             if lengt != 1 {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    "please, choose the cloud you want to use",
-                ));
+                return Err(OpenstackError::config("please, choose the cloud you want to use"));
             };
             region_copy = match &value["clouds"] {
                 serde_yaml::Value::Mapping(x) => match x.iter().next().unwrap().0.as_str() {
@@ -286,16 +402,14 @@ impl OpenstackInfoMap {
                 _ => "".to_string(),
             };
             if &region_copy == "" {
-                return Err(Error::new(ErrorKind::InvalidData, "invalid clouds.yaml"));
+                return Err(OpenstackError::config("invalid clouds.yaml"));
             };
         };
         if value["clouds"].get(&region_copy).is_none() {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "please, choose the cloud you want to use",
-            ));
+            return Err(OpenstackError::config("not found in clouds.yaml"));
         }
-        let auth_map: &serde_yaml::Value = &value["clouds"][&region_copy]["auth"];
+        let cloud = Self::resolve_profile(&value["clouds"][&region_copy], &vendors)?;
+        let auth_map: &serde_yaml::Value = &cloud["auth"];
         let serde_yaml_string = serde_yaml::Value::String("".to_string());
         let cloud_name: String = region_copy.clone();
         // let auth_url: String = auth_map
@@ -334,27 +448,25 @@ impl OpenstackInfoMap {
         //     .as_str()
         //     .unwrap()
         //     .to_string();
-        let tmp_info_map: Auth = match serde_yaml::from_value(auth_map.clone()) {
+        let mut tmp_info_map: Auth = match serde_yaml::from_value(auth_map.clone()) {
             Ok(x) => x,
-            Err(_e) => {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    "invalid clouds.yaml format",
-                ))
-            }
+            Err(_e) => return Err(OpenstackError::config("invalid clouds.yaml format")),
         };
-        let extra_map: &serde_yaml::Value = &value["clouds"][&region_copy];
+        let extra_map: &serde_yaml::Value = &cloud;
+        if let Some(x) = extra_map.get("auth_type").and_then(|x| x.as_str()) {
+            tmp_info_map.auth_type = x.to_string();
+        }
         let region_name: String = extra_map
             .get("region_name")
             .unwrap_or(&serde_yaml_string)
             .as_str()
             .unwrap()
             .to_string();
+        // without an interface the catalog could hand out internal or admin urls
         let interface: String = extra_map
             .get("interface")
-            .unwrap_or(&serde_yaml_string)
-            .as_str()
-            .unwrap()
+            .and_then(|x| x.as_str())
+            .unwrap_or(DEFAULT_INTERFACE)
             .to_string();
         // Ok(OpenstackInfoMap {
         //     cloud_name,
@@ -362,12 +474,68 @@ impl OpenstackInfoMap {
         //     interface,
         //     ..tmp_info_map
         // })
-        Ok(OpenstackInfoMap::new(
+        let mut info_map = OpenstackInfoMap::new(
             cloud_name,
             region_name,
             interface,
             tmp_info_map,
-        ))
+        );
+        info_map.token_expiry_margin = extra_map.get("token_expiry_margin").and_then(|x| x.as_i64());
+        info_map.api_retry_count = extra_map.get("api_retry_count").and_then(|x| x.as_u64()).map(|x| x as u32);
+        info_map.connect_retries = extra_map.get("connect_retries").and_then(|x| x.as_u64()).map(|x| x as u32);
+        info_map.cacert = extra_map.get("cacert").and_then(|x| x.as_str()).map(|x| x.to_string());
+        info_map.cert = extra_map.get("cert").and_then(|x| x.as_str()).map(|x| x.to_string());
+        info_map.key = extra_map.get("key").and_then(|x| x.as_str()).map(|x| x.to_string());
+        info_map.verify = extra_map.get("verify").and_then(|x| x.as_bool());
+        info_map.connect_timeout = extra_map.get("connect_timeout").and_then(|x| x.as_u64());
+        info_map.api_timeout = extra_map.get("api_timeout").and_then(|x| x.as_u64());
+        info_map.proxy = extra_map.get("proxy").and_then(|x| x.as_str()).map(|x| x.to_string());
+        info_map.no_proxy = extra_map.get("no_proxy").and_then(|x| x.as_str()).map(|x| x.to_string());
+        info_map.user_agent = extra_map.get("user_agent").and_then(|x| x.as_str()).map(|x| x.to_string());
+        if let serde_yaml::Value::Mapping(x) = extra_map {
+            for (key, value) in x.iter() {
+                if let (Some(y), Some(z)) = (key.as_str(), value.as_str()) {
+                    info_map.set_service_option(y, z.to_string());
+                }
+            }
+        }
+        Ok(info_map)
+    }
+
+    fn resolve_profile(
+        cloud: &serde_yaml::Value,
+        vendors: &serde_yaml::Value,
+    ) -> Result<serde_yaml::Value, OpenstackError> {
+        // 'cloud' is the old name of the 'profile' key
+        let profile_name = match cloud.get("profile").or_else(|| cloud.get("cloud")) {
+            Some(serde_yaml::Value::String(x)) => x.clone(),
+            _ => return Ok(cloud.clone()),
+        };
+        let mut profile = match vendors["public-clouds"].get(&profile_name) {
+            Some(x) => x.clone(),
+            None => {
+                return Err(OpenstackError::config(&format!(
+                    "profile '{}' not found in clouds-public.yaml",
+                    profile_name
+                )))
+            }
+        };
+        // vendor files nest the defaults in a 'profile' key
+        if let Some(x) = profile.get("profile").cloned() {
+            profile = x;
+        }
+        // vendor defaults only list the available regions, the first one is the default
+        if profile.get("region_name").is_none() {
+            let first_region = match &profile["regions"][0] {
+                serde_yaml::Value::String(x) => Some(x.clone()),
+                x => x["name"].as_str().map(|y| y.to_string()),
+            };
+            if let (Some(x), serde_yaml::Value::Mapping(y)) = (first_region, &mut profile) {
+                y.insert("region_name".into(), x.into());
+            }
+        }
+        merge_yaml(&mut profile, cloud);
+        Ok(profile)
     }
 
     pub fn from_env(region: String) -> OpenstackInfoMap {
@@ -386,29 +554,199 @@ impl OpenstackInfoMap {
         // let user_domain_id: String = std::env::var("OS_USER_DOMAIN_ID").unwrap_or("".to_string());
         let auth = Auth::from_env();
         let region_name: String = std::env::var("OS_REGION_NAME").unwrap_or("".to_string());
+        // empty when unset, so 'apply' keeps the interface of clouds.yaml or the default
         let interface: String = std::env::var("OS_INTERFACE").unwrap_or("".to_string());
 
-        OpenstackInfoMap::new(
+        let mut info_map = OpenstackInfoMap::new(
             cloud_name,
             region_name,
             interface,
             auth
-        )
+        );
+        info_map.token_expiry_margin = std::env::var("OS_TOKEN_EXPIRY_MARGIN")
+            .ok()
+            .and_then(|x| x.parse().ok());
+        info_map.api_retry_count = std::env::var("OS_API_RETRY_COUNT")
+            .ok()
+            .and_then(|x| x.parse().ok());
+        info_map.connect_retries = std::env::var("OS_CONNECT_RETRIES")
+            .ok()
+            .and_then(|x| x.parse().ok());
+        info_map.cacert = std::env::var("OS_CACERT").ok();
+        info_map.cert = std::env::var("OS_CERT").ok();
+        info_map.key = std::env::var("OS_KEY").ok();
+        info_map.verify = std::env::var("OS_INSECURE")
+            .ok()
+            .and_then(|x| parse_flag(&x))
+            .map(|x| !x);
+        info_map.connect_timeout = std::env::var("OS_CONNECT_TIMEOUT")
+            .ok()
+            .and_then(|x| x.parse().ok());
+        info_map.api_timeout = std::env::var("OS_API_TIMEOUT")
+            .ok()
+            .and_then(|x| x.parse().ok());
+        info_map.proxy = std::env::var("OS_PROXY").ok();
+        info_map.no_proxy = std::env::var("OS_NO_PROXY").ok();
+        info_map.user_agent = std::env::var("OS_USER_AGENT").ok();
+        for (key, value) in std::env::vars() {
+            if key.starts_with("OS_") {
+                info_map.set_service_option(&key[3..].to_lowercase(), value);
+            }
+        }
+        info_map
+    }
+
+    pub fn set_service_option(&mut self, key: &str, value: String) -> bool {
+        // keys look like 'compute_interface' or 'object_store_service_name'
+        for option in [
+            "interface",
+            "region_name",
+            "service_name",
+            "endpoint_override",
+            "api_version",
+        ]
+        .iter() {
+            let suffix = format!("_{}", option);
+            if key.ends_with(&suffix) && (key.len() > suffix.len()) {
+                let service_type = key[..key.len() - suffix.len()].replace("_", "-");
+                return self
+                    .services
+                    .entry(service_type)
+                    .or_insert_with(ServiceOptions::default)
+                    .set(option, value);
+            }
+        }
+        false
+    }
+
+    pub fn apply_endpoint_overrides(&self, endpoints: &mut HashMap<String, String>) {
+        for (service_type, options) in self.services.iter() {
+            if let Some(x) = &options.endpoint_override {
+                endpoints.insert(service_type.clone(), add_slash(x));
+            }
+        }
+    }
+
+    pub fn service_options(&self, service_type: &str) -> ServiceOptions {
+        self.services.get(service_type).cloned().unwrap_or_default()
+    }
+
+    pub fn token_expiry_margin(&self) -> Duration {
+        Duration::seconds(self.token_expiry_margin.unwrap_or(300))
+    }
+
+    pub fn client_options(&self) -> ClientOptions {
+        let mut options = ClientOptions {
+            cacert: self.cacert.clone(),
+            cert: self.cert.clone(),
+            key: self.key.clone(),
+            insecure: self.verify == Some(false),
+            proxy: self.proxy.clone(),
+            no_proxy: self.no_proxy.clone(),
+            debug_http: self.debug_http,
+            record: self.record.clone(),
+            replay: self.replay.clone(),
+            ..ClientOptions::default()
+        };
+        if let Some(x) = self.connect_timeout {
+            options.connect_timeout = std::time::Duration::from_secs(x);
+        }
+        // a timeout of 0 waits forever
+        match self.api_timeout {
+            Some(0) => options.timeout = None,
+            Some(x) => options.timeout = Some(std::time::Duration::from_secs(x)),
+            None => (),
+        };
+        if let Some(x) = &self.user_agent {
+            options.user_agent = format!("{} {}", x, options.user_agent);
+        }
+        options
     }
 
-    pub fn add_password(&mut self) -> Result<&mut Self, Error> {
+    pub fn retry_policy(&self) -> RetryPolicy {
+        let mut policy = RetryPolicy::default();
+        if let Some(x) = self.api_retry_count {
+            policy.status_retries = x;
+        }
+        if let Some(x) = self.connect_retries {
+            policy.connect_retries = x;
+        }
+        policy
+    }
+
+    pub fn add_password(&mut self) -> Result<&mut Self, OpenstackError> {
         let ps = match rpassword::prompt_password_stdout(&format!(
             "Openstack password for user '{}': ",
             self.auth.username
         )) {
             Ok(x) => x,
-            Err(e) => return Err(e),
+            Err(e) => {
+                return Err(OpenstackError::config(&format!(
+                    "no password given for user '{}': {}",
+                    self.auth.username, e
+                )))
+            }
         };
         self.auth.password = ps.into();
         Ok(self)
     }
 
-    pub fn add_password_if_not_existing(&mut self) -> Result<&mut Self, Error> {
+    pub fn add_passcode(&mut self) -> Result<&mut Self, OpenstackError> {
+        let ps = match rpassword::prompt_password_stdout(&format!(
+            "Openstack passcode for user '{}': ",
+            self.auth.username
+        )) {
+            Ok(x) => x,
+            Err(e) => {
+                return Err(OpenstackError::config(&format!(
+                    "no passcode given for user '{}': {}",
+                    self.auth.username, e
+                )))
+            }
+        };
+        self.auth.passcode = ps.into();
+        Ok(self)
+    }
+
+    pub fn add_passcode_if_needed(&mut self, methods: &[String]) -> Result<&mut Self, OpenstackError> {
+        if methods.contains(&"totp".to_string()) && (self.auth.passcode == Auth::default().passcode) {
+            return self.add_passcode();
+        }
+        Ok(self)
+    }
+
+    pub fn secret_key(&self) -> String {
+        // clouds set with env vars only don't have a name
+        let cloud = match self.cloud_name.as_ref() {
+            "" => &self.auth.auth_url,
+            x => x,
+        };
+        format!("{}/{}", cloud, self.auth.username)
+    }
+
+    pub fn add_password_from_store(&mut self, store: &dyn SecretStore) -> Result<&mut Self, OpenstackError> {
+        if self.auth.password != Auth::default().password {
+            return Ok(self);
+        }
+        let stored = store
+            .get(&self.secret_key())
+            .map_err(|e| OpenstackError::config(&format!("unable to read the secret store: {}", e)))?;
+        if let Some(x) = stored {
+            debug!("using password from secret store");
+            self.auth.password = x.into();
+        }
+        Ok(self)
+    }
+
+    pub fn add_password_if_not_existing(&mut self) -> Result<&mut Self, OpenstackError> {
+        if self.auth.uses_application_credential() | self.auth.uses_no_auth() {
+            return Ok(self);
+        }
+        if ((self.auth.auth_type == "v3totp") | (self.auth.auth_type == "v3multifactor"))
+            & !self.auth.auth_methods().contains(&"password".to_string())
+        {
+            return Ok(self);
+        }
         if self.auth.password == Auth::default().password {
             return self.add_password();
         }
@@ -445,12 +783,49 @@ impl OpenstackInfoMap {
         };
         if other.interface != "" {
             self.interface = other.interface.clone();
-            if self.interface == "public" {
-                self.only_use_public_endpoints = true
-            } else {
-                self.only_use_public_endpoints = false
-            }
         };
+        if other.token_expiry_margin.is_some() {
+            self.token_expiry_margin = other.token_expiry_margin;
+        };
+        if other.api_retry_count.is_some() {
+            self.api_retry_count = other.api_retry_count;
+        };
+        if other.connect_retries.is_some() {
+            self.connect_retries = other.connect_retries;
+        };
+        if other.cacert.is_some() {
+            self.cacert = other.cacert.clone();
+        };
+        if other.cert.is_some() {
+            self.cert = other.cert.clone();
+        };
+        if other.key.is_some() {
+            self.key = other.key.clone();
+        };
+        if other.verify.is_some() {
+            self.verify = other.verify;
+        };
+        if other.connect_timeout.is_some() {
+            self.connect_timeout = other.connect_timeout;
+        };
+        if other.api_timeout.is_some() {
+            self.api_timeout = other.api_timeout;
+        };
+        if other.proxy.is_some() {
+            self.proxy = other.proxy.clone();
+        };
+        if other.no_proxy.is_some() {
+            self.no_proxy = other.no_proxy.clone();
+        };
+        if other.user_agent.is_some() {
+            self.user_agent = other.user_agent.clone();
+        };
+        for (key, value) in other.services.iter() {
+            self.services
+                .entry(key.clone())
+                .or_insert_with(ServiceOptions::default)
+                .apply(value);
+        }
         self
     }
 }
@@ -467,13 +842,28 @@ impl Default for OpenstackInfoMap {
             // user_domain_id: String::from(""),
             auth: Auth::default(),
             region_name: String::from(""),
-            interface: String::from("public"),
-            only_use_public_endpoints: true,
+            interface: String::from(DEFAULT_INTERFACE),
+            token_expiry_margin: None,
+            services: HashMap::new(),
+            api_retry_count: None,
+            connect_retries: None,
+            cacert: None,
+            cert: None,
+            key: None,
+            verify: None,
+            connect_timeout: None,
+            api_timeout: None,
+            proxy: None,
+            no_proxy: None,
+            user_agent: None,
+            debug_http: false,
+            record: None,
+            replay: None,
         }
     }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 #[serde(default, rename_all = "snake_case")]
 pub struct Auth {
     pub user_id: String,
@@ -492,6 +882,14 @@ pub struct Auth {
     pub project_domain_id: String,
     pub project_domain_name: String,
     pub trust_id: String,
+    pub auth_type: String,
+    pub application_credential_id: String,
+    pub application_credential_name: String,
+    #[serde(skip_serializing)]
+    pub application_credential_secret: secstr::SecUtf8,
+    pub auth_methods: Vec<String>,
+    #[serde(skip_serializing)]
+    pub passcode: secstr::SecUtf8,
 }
 
 make_list!(Auth);
@@ -514,6 +912,12 @@ impl Default for Auth {
             project_domain_id: String::from(""),
             project_domain_name: String::from(""),
             trust_id: String::from(""),
+            auth_type: String::from(""),
+            application_credential_id: String::from(""),
+            application_credential_name: String::from(""),
+            application_credential_secret: secstr::SecUtf8::from(""),
+            auth_methods: vec![],
+            passcode: secstr::SecUtf8::from(""),
         }
     }
 }
@@ -592,35 +996,211 @@ impl Auth {
         let default: HashMap<String, serde_json::Value> = Self::default().into();
         let mut self_hm: HashMap<String, serde_json::Value> = self.into();
         let other_hm: HashMap<String, serde_json::Value> = other.into();
-        let password;
-        if other.password.unsecure() != Self::default().password.unsecure(){
-            password = other.password.clone();
-        } else {
-            password = self.password.clone();
-        }
         for (key, value) in default.iter(){
             if other_hm.get(key) != Some(&value){
                 self_hm.insert(key.clone(), other_hm.get(key).expect("this exists").clone());
             }
         }
         let mut new_auth = Auth::from(self_hm);
-        new_auth.password = password;
+        // secrets are not serialized, so they are not in the hashmaps
+        new_auth.password = pick_secret(&self.password, &other.password);
+        new_auth.application_credential_secret = pick_secret(
+            &self.application_credential_secret,
+            &other.application_credential_secret,
+        );
+        new_auth.passcode = pick_secret(&self.passcode, &other.passcode);
         new_auth
     }
 
-    pub fn pick_token_body(&self) -> serde_json::Value{
-        create_token_body(&self)
+    pub fn auth_methods(&self) -> Vec<String> {
+        // the keystone identity methods to use, such as "password" and "totp"
+        let default_auth = Auth::default();
+        if self.uses_application_credential() {
+            return vec!["application_credential".to_string()];
+        }
+        if (self.auth_type == "v3multifactor") & !self.auth_methods.is_empty() {
+            return self
+                .auth_methods
+                .iter()
+                .map(|x| match x.as_ref() {
+                    "v3password" => "password".to_string(),
+                    "v3totp" => "totp".to_string(),
+                    "v3token" => "token".to_string(),
+                    "v3applicationcredential" => "application_credential".to_string(),
+                    y => y.to_string(),
+                })
+                .collect();
+        }
+        if self.auth_type == "v3totp" {
+            return vec!["totp".to_string()];
+        }
+
+        let mut methods = vec![];
+        if self.password != default_auth.password {
+            methods.push("password".to_string());
+        } else if self.token != default_auth.token {
+            methods.push("token".to_string());
+        }
+        if self.passcode != default_auth.passcode {
+            methods.push("totp".to_string());
+        }
+        methods
+    }
+
+    pub fn uses_no_auth(&self) -> bool {
+        // for services without keystone, used together with endpoint overrides
+        self.auth_type == "none"
+    }
+
+    pub fn uses_application_credential(&self) -> bool {
+        let default_auth = Auth::default();
+        if self.auth_type != default_auth.auth_type {
+            return self.auth_type == "v3applicationcredential";
+        }
+        self.application_credential_secret != default_auth.application_credential_secret
+    }
+
+    pub fn scopes(&self) -> Vec<&'static str> {
+        let default_auth = Auth::default();
+        let mut scopes = vec![];
+        if (self.project_id != default_auth.project_id) | (self.project_name != default_auth.project_name) {
+            scopes.push("project");
+        }
+        if (self.domain_id != default_auth.domain_id) | (self.domain_name != default_auth.domain_name) {
+            scopes.push("domain");
+        }
+        if self.system_scope != default_auth.system_scope {
+            scopes.push("system");
+        }
+        if self.trust_id != default_auth.trust_id {
+            scopes.push("trust");
+        }
+        scopes
+    }
+
+    pub fn check_scope(&self) -> Result<(), OpenstackError> {
+        let scopes = self.scopes();
+        if scopes.len() > 1 {
+            return Err(OpenstackError::config(&format!(
+                "Conflicting scopes: {}. Only one of project, domain, system or trust can be used",
+                scopes.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn pick_token_body(&self) -> Result<serde_json::Value, OpenstackError>{
+        self.check_scope()?;
+        Ok(create_token_body(&self))
     }
 
     pub fn create_hash(&self) -> String{
         let mut s = sha2::Sha256::new();
-        s.input(bincode::serialize(self).unwrap());
-        format!("{:.16X}", s.result())
+        s.update(bincode::serialize(self).unwrap());
+        format!("{:.16X}", s.finalize())
+    }
+}
+
+
+pub fn missing_auth_methods(data: &serde_json::Value) -> Vec<String> {
+    // picks the auth rule that needs the least extra methods
+    let received: Vec<&str> = match data["receipt"]["methods"].as_array() {
+        Some(x) => x.iter().filter_map(|y| y.as_str()).collect(),
+        None => vec![],
+    };
+    let mut missing_methods: Option<Vec<String>> = None;
+    if let Some(rules) = data["required_auth_methods"].as_array() {
+        for rule in rules {
+            let missing: Vec<String> = match rule.as_array() {
+                Some(x) => x
+                    .iter()
+                    .filter_map(|y| y.as_str())
+                    .filter(|y| !received.contains(y))
+                    .map(|y| y.to_string())
+                    .collect(),
+                None => continue,
+            };
+            let is_better = match &missing_methods {
+                Some(x) => missing.len() < x.len(),
+                None => true,
+            };
+            if is_better && !missing.is_empty() {
+                missing_methods = Some(missing);
+            }
+        }
+    }
+    missing_methods.unwrap_or_default()
+}
+
+// environment flags are spelled like the python clients accept them, 1, yes, True and so on
+fn parse_flag(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "t" | "true" | "on" | "y" | "yes" => Some(true),
+        "0" | "f" | "false" | "off" | "n" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+fn pick_secret(current: &secstr::SecUtf8, other: &secstr::SecUtf8) -> secstr::SecUtf8 {
+    if other.unsecure() != "" {
+        return other.clone();
+    }
+    current.clone()
+}
+
+pub fn config_file_locations(env_key: Option<&str>, filename: &str) -> Vec<PathBuf> {
+    // same search order as os-client-config:
+    // the file from the environment variable
+    // current directory
+    // ~/.config/openstack
+    // /etc/openstack
+    let mut locations = vec![];
+    if let Some(x) = env_key.and_then(std::env::var_os) {
+        locations.push(PathBuf::from(x));
+    }
+    locations.push(PathBuf::from(filename));
+    if let Some(x) = user_config_dir() {
+        locations.push(x.join("openstack").join(filename));
+    }
+    if !cfg!(windows) {
+        locations.push(PathBuf::from("/etc/openstack").join(filename));
     }
+    locations
 }
 
+pub fn find_config_file(env_key: Option<&str>, filename: &str) -> Option<PathBuf> {
+    config_file_locations(env_key, filename)
+        .into_iter()
+        .find(|x| x.is_file())
+}
 
 pub fn create_token_body(auth: &Auth) -> serde_json::Value{
+    create_token_body_for_methods(auth, &auth.auth_methods())
+}
+
+fn create_user_body(auth: &Auth) -> serde_json::Value {
+    let default_auth = Auth::default();
+    let mut user = json!({});
+    if auth.username != default_auth.username {
+        user["name"] = json!(auth.username)
+    }
+    if auth.user_id != default_auth.user_id {
+        user["id"] = json!(auth.user_id)
+    }
+    if auth.user_domain_name != default_auth.user_domain_name {
+        user["domain"] = json!({
+            "name": auth.user_domain_name
+        })
+    }
+    if auth.user_domain_id != default_auth.user_domain_id {
+        user["domain"] = json!({
+            "id": auth.user_domain_id
+        })
+    }
+    user
+}
+
+pub fn create_token_body_for_methods(auth: &Auth, methods: &[String]) -> serde_json::Value{
 
 // {
 //     "auth": {
@@ -671,61 +1251,62 @@ pub fn create_token_body(auth: &Auth) -> serde_json::Value{
     let default_auth = Auth::default();
     let mut body = json!({});
 
-    // pick token or password method
-    if auth.token != default_auth.token {
-        body = json!({
-            "auth": {
-                "identity": {
-                    "methods": [
-                        "token"
-                    ],
-                    "token": {
-                        "id": auth.token
-                    }
-                }
-            }
-        });
+    if !methods.is_empty() {
+        body["auth"]["identity"]["methods"] = json!(methods);
     }
-
-    if auth.password != default_auth.password {
-        body = json!({
-            "auth": {
-                "identity": {
-                    "methods": ["password"],
-                    "password": {
-                        "user": {
-                            "password": auth.password.unsecure()
-                        }
-                    }
+    for method in methods {
+        match method.as_ref() {
+            "token" => {
+                body["auth"]["identity"]["token"] = json!({
+                    "id": auth.token
+                });
+            }
+            "password" => {
+                let mut user = create_user_body(auth);
+                user["password"] = json!(auth.password.unsecure());
+                body["auth"]["identity"]["password"] = json!({ "user": user });
+            }
+            "totp" => {
+                let mut user = create_user_body(auth);
+                user["passcode"] = json!(auth.passcode.unsecure());
+                body["auth"]["identity"]["totp"] = json!({ "user": user });
+            }
+            "application_credential" => {
+                let mut credential = json!({
+                    "secret": auth.application_credential_secret.unsecure()
+                });
+                if auth.application_credential_id != default_auth.application_credential_id {
+                    credential["id"] = json!(auth.application_credential_id);
+                } else {
+                    credential["name"] = json!(auth.application_credential_name);
+                    credential["user"] = create_user_body(auth);
                 }
+                body["auth"]["identity"]["application_credential"] = credential;
+                // application credentials are already scoped, so no scope is added
+                return body;
             }
-        });
-        if auth.username != default_auth.username{
-            body["auth"]["identity"]["password"]["user"]["name"] = json!(auth.username)
-        }
-        if auth.user_id != default_auth.user_id{
-            body["auth"]["identity"]["password"]["user"]["id"] = json!(auth.user_id)
-        }
-
-        if auth.user_domain_name != default_auth.user_domain_name {
-            body["auth"]["identity"]["password"]["user"]["domain"] = json!({
-                "name": auth.user_domain_name
-            })
+            _ => (),
         }
-
-        if auth.user_domain_id != default_auth.user_domain_id {
-            body["auth"]["identity"]["password"]["user"]["domain"] = json!({
-                "id": auth.user_domain_id
-            })
-        }
-
     }
 
     // set scope or set scope as 'unscoped'
+    // conflicting scopes are rejected by Auth::check_scope
 
-    // if auth.system_scope !=  default_auth.system_scope{
-    //     body[""]
-    // }
+    if auth.system_scope != default_auth.system_scope {
+        body["auth"]["scope"] = json!({
+            "system": {
+                (auth.system_scope.clone()): true
+            }
+        });
+    }
+
+    if auth.trust_id != default_auth.trust_id {
+        body["auth"]["scope"] = json!({
+            "OS-TRUST:trust": {
+                "id": auth.trust_id
+            }
+        });
+    }
 
     if auth.domain_id != default_auth.domain_id {
         body["auth"]["scope"] = json!({
@@ -749,7 +1330,16 @@ pub fn create_token_body(auth: &Auth) -> serde_json::Value{
         body["auth"]["scope"]["project"] = json!({"id": auth.project_id});
     }
 
-    if auth.project_domain_id != default_auth.project_domain_id{
+    // openrc files export the project domain for every scope, it only belongs to a project
+    let has_project = body["auth"]["scope"].get("project").is_some();
+
+    if has_project && auth.project_domain_name != default_auth.project_domain_name{
+        body["auth"]["scope"]["project"]["domain"] = json!({
+            "name": auth.project_domain_name
+        });
+    }
+
+    if has_project && auth.project_domain_id != default_auth.project_domain_id{
         body["auth"]["scope"]["project"]["domain"] = json!({
             "id": auth.project_domain_id
         });
@@ -908,28 +1498,161 @@ fn test_parse_clouds_yaml_fails_when_wrong_type() {
 }
 
 #[test]
-fn test_auth_from_hashmap() {
-    let mut hm: HashMap<String, String> = HashMap::new();
-    hm.insert("project_id".to_string(), "1234".to_string());
-    hm.insert("user_id".to_string(), "4321".to_string());
-    hm.insert("password".to_string(), "secret_password".to_string());
+fn test_parse_clouds_yaml_uses_profile_defaults() {
+    let raw_clouds_yaml = r#"
+    clouds:
+      cloud:
+        profile: vendor
+        auth:
+          username: "test_user"
+          password: "secret_password"
+    "#;
+    let raw_vendor_yaml = r#"
+    public-clouds:
+      vendor:
+        auth:
+          auth_url: "https://identity.example.com"
+        region_name: "test"
+        interface: "internal"
+    "#;
+    let yaml = serde_yaml::from_str(raw_clouds_yaml).unwrap();
+    let vendors = serde_yaml::from_str(raw_vendor_yaml).unwrap();
 
-    let expected = Auth {
-        project_id: "1234".to_string(),
-        user_id: "4321".to_string(),
-        password: secstr::SecUtf8::from("secret_password"),
-        ..Default::default()
-    };
-    assert_eq!(Auth::from(hm), expected);
+    let config = OpenstackInfoMap::parse_clouds_yaml_with_vendors(yaml, vendors, String::from("")).unwrap();
+    assert_eq!(config.auth.auth_url, "https://identity.example.com");
+    assert_eq!(config.auth.username, "test_user");
+    assert_eq!(config.region_name, "test");
+    assert_eq!(config.interface, "internal");
 }
 
 #[test]
-fn test_auth_apply_works(){
-    let first_auth = Auth {
-        project_id: "1234".to_string(),
-        user_id: "4321".to_string(),
-        ..Default::default()
-    };
+fn test_parse_clouds_yaml_cloud_values_override_profile_defaults() {
+    let raw_clouds_yaml = r#"
+    clouds:
+      cloud:
+        profile: vendor
+        auth:
+          auth_url: "https://other-identity.example.com"
+          username: "test_user"
+        region_name: "other"
+    "#;
+    let raw_vendor_yaml = r#"
+    public-clouds:
+      vendor:
+        profile:
+          auth:
+            auth_url: "https://identity.example.com"
+          regions:
+          - test
+          - other
+    "#;
+    let yaml = serde_yaml::from_str(raw_clouds_yaml).unwrap();
+    let vendors = serde_yaml::from_str(raw_vendor_yaml).unwrap();
+
+    let config = OpenstackInfoMap::parse_clouds_yaml_with_vendors(yaml, vendors, String::from("cloud")).unwrap();
+    assert_eq!(config.auth.auth_url, "https://other-identity.example.com");
+    assert_eq!(config.region_name, "other");
+}
+
+#[test]
+fn test_parse_clouds_yaml_profile_picks_first_region() {
+    let raw_clouds_yaml = r#"
+    clouds:
+      cloud:
+        profile: vendor
+    "#;
+    let raw_vendor_yaml = r#"
+    public-clouds:
+      vendor:
+        auth:
+          auth_url: "https://identity.example.com"
+        regions:
+        - name: test
+        - name: other
+    "#;
+    let yaml = serde_yaml::from_str(raw_clouds_yaml).unwrap();
+    let vendors = serde_yaml::from_str(raw_vendor_yaml).unwrap();
+
+    let config = OpenstackInfoMap::parse_clouds_yaml_with_vendors(yaml, vendors, String::from("")).unwrap();
+    assert_eq!(config.region_name, "test");
+}
+
+#[test]
+fn test_parse_clouds_yaml_fails_when_profile_is_unknown() {
+    let raw_clouds_yaml = r#"
+    clouds:
+      cloud:
+        profile: vendor
+    "#;
+    let yaml = serde_yaml::from_str(raw_clouds_yaml).unwrap();
+
+    let error = OpenstackInfoMap::parse_clouds_yaml(yaml, String::from("")).unwrap_err();
+    assert_eq!(error.kind(), "config");
+    assert_eq!(error.exit_code(), 2);
+}
+
+#[test]
+fn test_parse_clouds_yaml_uses_merged_secure_yaml() {
+    let raw_clouds_yaml = r#"
+    clouds:
+      cloud:
+        auth:
+          auth_url: "https://identity.example.com"
+          username: "test_user"
+        region_name: "test"
+    "#;
+    let raw_secure_yaml = r#"
+    clouds:
+      cloud:
+        auth:
+          password: "secret_password"
+    "#;
+    let mut yaml = serde_yaml::from_str(raw_clouds_yaml).unwrap();
+    merge_yaml(&mut yaml, &serde_yaml::from_str(raw_secure_yaml).unwrap());
+
+    let config = OpenstackInfoMap::parse_clouds_yaml(yaml, String::from("")).unwrap();
+    assert_eq!(config.auth.auth_url, "https://identity.example.com");
+    assert_eq!(config.auth.username, "test_user");
+    assert_eq!(config.auth.password.unsecure(), "secret_password");
+}
+
+#[test]
+fn test_config_file_locations_search_order() {
+    std::env::set_var("OS_TEST_CONFIG_FILE_LOCATIONS", "/tmp/my-clouds.yaml");
+    let locations = config_file_locations(Some("OS_TEST_CONFIG_FILE_LOCATIONS"), "clouds.yaml");
+    std::env::remove_var("OS_TEST_CONFIG_FILE_LOCATIONS");
+
+    assert_eq!(locations[0], PathBuf::from("/tmp/my-clouds.yaml"));
+    assert_eq!(locations[1], PathBuf::from("clouds.yaml"));
+    assert!(locations[2].ends_with("openstack/clouds.yaml"));
+    if !cfg!(windows) {
+        assert_eq!(locations[locations.len() - 1], PathBuf::from("/etc/openstack/clouds.yaml"));
+    }
+}
+
+#[test]
+fn test_auth_from_hashmap() {
+    let mut hm: HashMap<String, String> = HashMap::new();
+    hm.insert("project_id".to_string(), "1234".to_string());
+    hm.insert("user_id".to_string(), "4321".to_string());
+    hm.insert("password".to_string(), "secret_password".to_string());
+
+    let expected = Auth {
+        project_id: "1234".to_string(),
+        user_id: "4321".to_string(),
+        password: secstr::SecUtf8::from("secret_password"),
+        ..Default::default()
+    };
+    assert_eq!(Auth::from(hm), expected);
+}
+
+#[test]
+fn test_auth_apply_works(){
+    let first_auth = Auth {
+        project_id: "1234".to_string(),
+        user_id: "4321".to_string(),
+        ..Default::default()
+    };
 
     let second_auth = Auth {
         project_id: "4567".to_string(),
@@ -1084,6 +1807,291 @@ fn test_create_token_body_picks_correct_body_and_set_unscoped(){
     assert_eq!(create_token_body(&auth), expected);
 }
 
+#[test]
+fn test_create_token_body_picks_correct_body_application_credential_id(){
+    let auth = Auth {
+        auth_type: "v3applicationcredential".to_string(),
+        application_credential_id: "1234".to_string(),
+        application_credential_secret: secstr::SecUtf8::from("secret"),
+        project_id: "4567".to_string(),
+        ..Default::default()
+    };
+
+    let expected = json!({
+        "auth": {
+            "identity": {
+                "methods": ["application_credential"],
+                "application_credential": {
+                    "id": "1234",
+                    "secret": "secret"
+                }
+            }
+        }
+    });
+
+    assert_eq!(create_token_body(&auth), expected);
+}
+
+#[test]
+fn test_create_token_body_picks_correct_body_application_credential_name(){
+    let auth = Auth {
+        application_credential_name: "ci".to_string(),
+        application_credential_secret: secstr::SecUtf8::from("secret"),
+        username: "test".to_string(),
+        user_domain_id: "4321".to_string(),
+        ..Default::default()
+    };
+
+    let expected = json!({
+        "auth": {
+            "identity": {
+                "methods": ["application_credential"],
+                "application_credential": {
+                    "name": "ci",
+                    "secret": "secret",
+                    "user": {
+                        "name": "test",
+                        "domain": {
+                            "id": "4321"
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    assert_eq!(create_token_body(&auth), expected);
+}
+
+#[test]
+fn test_create_token_body_picks_correct_body_multifactor(){
+    let auth = Auth {
+        username: "test".to_string(),
+        user_domain_id: "4321".to_string(),
+        password: secstr::SecUtf8::from("password"),
+        passcode: secstr::SecUtf8::from("123456"),
+        auth_type: "v3multifactor".to_string(),
+        auth_methods: vec!["v3password".to_string(), "v3totp".to_string()],
+        ..Default::default()
+    };
+
+    let expected = json!({
+        "auth": {
+            "identity": {
+                "methods": ["password", "totp"],
+                "password": {
+                    "user": {
+                        "name": "test",
+                        "password": "password",
+                        "domain": {
+                            "id": "4321"
+                        }
+                    }
+                },
+                "totp": {
+                    "user": {
+                        "name": "test",
+                        "passcode": "123456",
+                        "domain": {
+                            "id": "4321"
+                        }
+                    }
+                }
+            },
+            "scope": "unscoped"
+        }
+    });
+
+    assert_eq!(create_token_body(&auth), expected);
+}
+
+#[test]
+fn test_create_token_body_picks_correct_body_totp(){
+    let auth = Auth {
+        user_id: "1234".to_string(),
+        passcode: secstr::SecUtf8::from("123456"),
+        auth_type: "v3totp".to_string(),
+        ..Default::default()
+    };
+
+    let expected = json!({
+        "auth": {
+            "identity": {
+                "methods": ["totp"],
+                "totp": {
+                    "user": {
+                        "id": "1234",
+                        "passcode": "123456"
+                    }
+                }
+            },
+            "scope": "unscoped"
+        }
+    });
+
+    assert_eq!(create_token_body(&auth), expected);
+}
+
+#[test]
+fn test_create_token_body_picks_correct_body_system_scope(){
+    let auth = Auth {
+        token: "abcdefg".to_string(),
+        system_scope: "all".to_string(),
+        ..Default::default()
+    };
+
+    let expected = json!({
+        "auth": {
+            "identity": {
+                "methods": ["token"],
+                "token": {
+                    "id": "abcdefg"
+                }
+            },
+            "scope": {
+                "system": {
+                    "all": true
+                }
+            }
+        }
+    });
+
+    assert_eq!(auth.pick_token_body().unwrap(), expected);
+}
+
+#[test]
+fn test_create_token_body_picks_correct_body_trust(){
+    let auth = Auth {
+        token: "abcdefg".to_string(),
+        trust_id: "1234".to_string(),
+        ..Default::default()
+    };
+
+    let expected = json!({
+        "auth": {
+            "identity": {
+                "methods": ["token"],
+                "token": {
+                    "id": "abcdefg"
+                }
+            },
+            "scope": {
+                "OS-TRUST:trust": {
+                    "id": "1234"
+                }
+            }
+        }
+    });
+
+    assert_eq!(auth.pick_token_body().unwrap(), expected);
+}
+
+#[test]
+fn test_project_domain_is_ignored_without_a_project(){
+    let system = Auth {
+        token: "abcdefg".to_string(),
+        system_scope: "all".to_string(),
+        project_domain_name: "Default".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(system.pick_token_body().unwrap()["auth"]["scope"], json!({"system": {"all": true}}));
+
+    let trust = Auth {
+        token: "abcdefg".to_string(),
+        trust_id: "1234".to_string(),
+        project_domain_name: "Default".to_string(),
+        project_domain_id: "default".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(trust.pick_token_body().unwrap()["auth"]["scope"], json!({"OS-TRUST:trust": {"id": "1234"}}));
+
+    let unscoped = Auth {
+        token: "abcdefg".to_string(),
+        project_domain_id: "default".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(unscoped.pick_token_body().unwrap()["auth"]["scope"], json!("unscoped"));
+
+    let project = Auth {
+        token: "abcdefg".to_string(),
+        project_name: "admin".to_string(),
+        project_domain_name: "Default".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(
+        project.pick_token_body().unwrap()["auth"]["scope"],
+        json!({"project": {"name": "admin", "domain": {"name": "Default"}}})
+    );
+}
+
+#[test]
+fn test_token_response_without_subject_token_is_an_auth_error(){
+    let mut tokenizer = OpenstackTokenizer::new(OpenstackInfoMap::default());
+    let headers = ::headers::HeaderMap::from_json(&json!({"content-type": "application/json"}));
+    let error = tokenizer.parse_token_reponse(&Response(json!({}), 201, headers)).unwrap_err();
+    assert_eq!(error.kind(), "auth");
+    assert_eq!(error.exit_code(), 3);
+
+    let headers = ::headers::HeaderMap::from_json(&json!({"X-Subject-Token": "abcdefg"}));
+    tokenizer.parse_token_reponse(&Response(json!({}), 201, headers)).unwrap();
+    assert_eq!(tokenizer.token, Some("abcdefg".to_string()));
+}
+
+#[test]
+fn test_pick_token_body_rejects_conflicting_scopes(){
+    let auth = Auth {
+        token: "abcdefg".to_string(),
+        project_id: "1234".to_string(),
+        domain_id: "4321".to_string(),
+        system_scope: "all".to_string(),
+        ..Default::default()
+    };
+
+    let error = auth.pick_token_body().unwrap_err();
+    assert_eq!(error.kind(), "config");
+    assert!(error.to_string().contains("project, domain, system"));
+}
+
+#[test]
+fn test_missing_auth_methods_picks_smallest_rule(){
+    let data = json!({
+        "receipt": {
+            "methods": ["password"]
+        },
+        "required_auth_methods": [
+            ["password", "totp", "token"],
+            ["password", "totp"]
+        ]
+    });
+
+    assert_eq!(missing_auth_methods(&data), vec!["totp".to_string()]);
+}
+
+#[test]
+fn test_missing_auth_methods_without_receipt(){
+    assert_eq!(missing_auth_methods(&json!({})), Vec::<String>::new());
+}
+
+#[test]
+fn test_parse_clouds_yaml_reads_application_credential() {
+    let raw_clouds_yaml = r#"
+    clouds:
+      cloud:
+        auth_type: "v3applicationcredential"
+        auth:
+          auth_url: "https://identity.example.com"
+          application_credential_id: "1234"
+          application_credential_secret: "secret"
+    "#;
+    let yaml = serde_yaml::from_str(raw_clouds_yaml).unwrap();
+
+    let mut config = OpenstackInfoMap::parse_clouds_yaml(yaml, String::from("")).unwrap();
+    assert!(config.auth.uses_application_credential());
+    assert_eq!(config.auth.application_credential_secret.unsecure(), "secret");
+    // does not prompt for a password
+    assert!(config.add_password_if_not_existing().is_ok());
+}
+
 // example setup teardown function
 // fn run_test<T>(test: T) -> ()
 //     where T: FnOnce() -> () + std::panic::UnwindSafe
@@ -1147,5 +2155,207 @@ fn test_auth_create_hash(){
         "password": "password",
         "auth_url": "https://example.com"
     })).unwrap();
-    assert_eq!("E78E50ECD12BFBAA", auth.create_hash())
-}
\ No newline at end of file
+    assert_eq!("365E5DF5E79EDC41", auth.create_hash())
+}
+
+#[test]
+fn test_token_expires_within_margin() {
+    let mut tokenizer = OpenstackTokenizer::new(OpenstackInfoMap::default());
+    tokenizer.token = Some("token".to_string());
+
+    tokenizer.token_expiry = Some((Utc::now() + Duration::minutes(2)).to_rfc3339());
+    assert!(tokenizer.token_expires_within(Duration::minutes(5)));
+    assert!(tokenizer.needs_refresh());
+
+    tokenizer.token_expiry = Some("2270-06-02T21:16:34.000000Z".to_string());
+    assert!(!tokenizer.token_expires_within(Duration::minutes(5)));
+    assert!(!tokenizer.needs_refresh());
+
+    tokenizer.token_expiry = None;
+    assert!(!tokenizer.needs_refresh());
+
+    tokenizer.token = None;
+    assert!(tokenizer.needs_refresh());
+}
+
+#[test]
+fn test_parse_clouds_yaml_reads_token_expiry_margin() {
+    let raw_clouds_yaml = r#"
+    clouds:
+      cloud:
+        token_expiry_margin: 60
+        auth:
+          auth_url: "https://identity.example.com"
+    "#;
+    let value: serde_yaml::Value = serde_yaml::from_str(raw_clouds_yaml).unwrap();
+    let config = OpenstackInfoMap::parse_clouds_yaml(value, "cloud".to_string()).unwrap();
+
+    assert_eq!(config.token_expiry_margin(), Duration::seconds(60));
+    assert_eq!(OpenstackInfoMap::default().token_expiry_margin(), Duration::minutes(5));
+}
+
+#[test]
+fn test_parse_clouds_yaml_reads_retry_policy() {
+    let raw_clouds_yaml = r#"
+    clouds:
+      cloud:
+        api_retry_count: 5
+        connect_retries: 0
+        auth:
+          auth_url: "https://identity.example.com"
+    "#;
+    let value: serde_yaml::Value = serde_yaml::from_str(raw_clouds_yaml).unwrap();
+    let config = OpenstackInfoMap::parse_clouds_yaml(value, "cloud".to_string()).unwrap();
+    let policy = config.retry_policy();
+
+    assert_eq!(policy.status_retries, 5);
+    assert_eq!(policy.connect_retries, 0);
+    assert_eq!(OpenstackInfoMap::default().retry_policy(), RetryPolicy::default());
+}
+
+#[test]
+fn test_parse_clouds_yaml_reads_tls_options() {
+    let raw_clouds_yaml = r#"
+    clouds:
+      cloud:
+        cacert: "/etc/ssl/internal-ca.pem"
+        cert: "/home/user/client.pem"
+        key: "/home/user/client.key"
+        verify: false
+        auth:
+          auth_url: "https://identity.example.com"
+    "#;
+    let value: serde_yaml::Value = serde_yaml::from_str(raw_clouds_yaml).unwrap();
+    let config = OpenstackInfoMap::parse_clouds_yaml(value, "cloud".to_string()).unwrap();
+    let options = config.client_options();
+
+    assert_eq!(options.cacert, Some("/etc/ssl/internal-ca.pem".to_string()));
+    assert_eq!(options.cert, Some("/home/user/client.pem".to_string()));
+    assert_eq!(options.key, Some("/home/user/client.key".to_string()));
+    assert!(options.insecure);
+    assert!(!OpenstackInfoMap::default().client_options().insecure);
+}
+
+#[test]
+fn test_parse_flag_accepts_common_spellings() {
+    for x in &["1", "true", "True", "YES", "on", " y "] {
+        assert_eq!(parse_flag(x), Some(true), "{}", x);
+    }
+    for x in &["0", "false", "False", "no", "OFF"] {
+        assert_eq!(parse_flag(x), Some(false), "{}", x);
+    }
+    assert_eq!(parse_flag("maybe"), None);
+    assert_eq!(parse_flag(""), None);
+}
+
+#[test]
+fn test_parse_clouds_yaml_reads_http_options() {
+    let raw_clouds_yaml = r#"
+    clouds:
+      cloud:
+        connect_timeout: 5
+        api_timeout: 0
+        proxy: "socks5h://proxy.example.com:1080"
+        no_proxy: "localhost,.internal"
+        user_agent: "my-script/1.0"
+        auth:
+          auth_url: "https://identity.example.com"
+    "#;
+    let value: serde_yaml::Value = serde_yaml::from_str(raw_clouds_yaml).unwrap();
+    let config = OpenstackInfoMap::parse_clouds_yaml(value, "cloud".to_string()).unwrap();
+    let options = config.client_options();
+
+    assert_eq!(options.connect_timeout, std::time::Duration::from_secs(5));
+    assert_eq!(options.timeout, None);
+    assert_eq!(options.proxy, Some("socks5h://proxy.example.com:1080".to_string()));
+    assert_eq!(options.no_proxy, Some("localhost,.internal".to_string()));
+    assert!(options.user_agent.starts_with("my-script/1.0 openstack-client/"));
+    assert_eq!(OpenstackInfoMap::default().client_options(), ClientOptions::default());
+}
+
+#[test]
+fn test_add_password_from_store() {
+    use secrets::FileStore;
+    let path = std::env::temp_dir()
+        .join(format!("openstack-client-secrets-{}", std::process::id()))
+        .join("config.json");
+    let store = FileStore::new(path);
+    store.set("cloud/username", "password").unwrap();
+
+    let mut config = OpenstackInfoMap::default();
+    config.cloud_name = "cloud".to_string();
+    config.auth.username = "username".to_string();
+    config.add_password_from_store(&store).unwrap();
+    assert_eq!(config.auth.password.unsecure(), "password");
+
+    config.auth.password = "other".into();
+    config.add_password_from_store(&store).unwrap();
+    assert_eq!(config.auth.password.unsecure(), "other");
+}
+
+#[test]
+fn test_parse_clouds_yaml_reads_service_options() {
+    let raw_clouds_yaml = r#"
+    clouds:
+      cloud:
+        region_name: "ams"
+        interface: "public"
+        compute_interface: "internal"
+        compute_endpoint_override: "https://proxy.example.com/compute"
+        compute_api_version: "2.53"
+        object_store_service_name: "swift"
+        volumev3_region_name: "rtm"
+        auth:
+          auth_url: "https://identity.example.com"
+    "#;
+    let value: serde_yaml::Value = serde_yaml::from_str(raw_clouds_yaml).unwrap();
+    let config = OpenstackInfoMap::parse_clouds_yaml(value, "cloud".to_string()).unwrap();
+
+    assert_eq!(config.service_options("compute").interface, Some("internal".to_string()));
+    assert_eq!(config.service_options("compute").api_version, Some("2.53".to_string()));
+    assert_eq!(config.service_options("object-store").service_name, Some("swift".to_string()));
+    assert_eq!(config.service_options("volumev3").region_name, Some("rtm".to_string()));
+    assert_eq!(config.service_options("image"), ServiceOptions::default());
+    assert_eq!(config.services.len(), 3);
+
+    let mut endpoints = HashMap::new();
+    endpoints.insert("compute".to_string(), "https://compute.internal/".to_string());
+    endpoints.insert("image".to_string(), "https://image.example.com/".to_string());
+    config.apply_endpoint_overrides(&mut endpoints);
+    assert_eq!(endpoints["compute"], "https://proxy.example.com/compute/");
+    assert_eq!(endpoints["image"], "https://image.example.com/");
+}
+
+#[test]
+fn test_interface_defaults_to_public() {
+    let raw_clouds_yaml = r#"
+    clouds:
+      cloud:
+        auth:
+          auth_url: "https://identity.example.com"
+          username: "test_user"
+          password: "secret_password"
+        region_name: "ams"
+    "#;
+    let yaml = serde_yaml::from_str(raw_clouds_yaml).unwrap();
+    let mut config = OpenstackInfoMap::parse_clouds_yaml(yaml, String::from("")).unwrap();
+    // OS_INTERFACE is not set
+    config.apply(&OpenstackInfoMap::new(String::new(), String::new(), String::new(), Auth::default()));
+    assert_eq!(config.interface, "public");
+
+    let catalog = ServiceCatalog::from_token_response(&json!({
+        "token": {
+            "catalog": [{
+                "id": "1",
+                "name": "nova",
+                "type": "compute",
+                "endpoints": [
+                    {"id": "a", "interface": "internal", "region_id": "ams", "region": "ams", "url": "http://compute.internal/v2.1"},
+                    {"id": "b", "interface": "admin", "region_id": "ams", "region": "ams", "url": "http://compute.admin/v2.1"},
+                    {"id": "c", "interface": "public", "region_id": "ams", "region": "ams", "url": "https://compute.example.com/v2.1"}
+                ]
+            }]
+        }
+    }));
+    assert_eq!(catalog.endpoints(&config)["compute"], "https://compute.example.com/v2.1/");
+}
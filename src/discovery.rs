@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+
+use url::Url;
+
+use error::OpenstackError;
+use utils::add_slash;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct ServiceVersion {
+    pub id: String,
+    pub status: String,
+    pub url: String,
+    // the supported microversion range, empty when the service has no microversions
+    pub min_version: String,
+    pub version: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct VersionSpec {
+    pub default: String,
+    pub supported: Vec<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct MicroversionSpec {
+    // the prefix of the config key, 'compute' for 'compute_api_version'
+    pub option: String,
+    pub header: String,
+    // the service name that prefixes the value of 'OpenStack-API-Version'
+    pub service: String,
+}
+
+impl MicroversionSpec {
+    pub fn header_value(&self, version: &str) -> String {
+        match self.service.as_ref() {
+            "" => version.to_string(),
+            x => format!("{} {}", x, version),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct VersionSpecs {
+    pub versions: HashMap<String, VersionSpec>,
+    pub aliases: HashMap<String, Vec<String>>,
+    pub microversions: HashMap<String, MicroversionSpec>,
+}
+
+impl VersionSpecs {
+    pub fn new() -> Self {
+        let raw_string_yaml = include_str!("../data/resource_types.yaml");
+        serde_yaml::from_str(raw_string_yaml).expect("resource_types.yaml is not valid yaml")
+    }
+}
+
+impl ServiceVersion {
+    fn from_value(data: &serde_json::Value) -> Self {
+        let url = data["links"]
+            .as_array()
+            .and_then(|x| x.iter().find(|y| y["rel"] == "self"))
+            .and_then(|x| x["href"].as_str())
+            .unwrap_or("")
+            .to_string();
+        let get = |key: &str| data[key].as_str().unwrap_or("").to_string();
+        ServiceVersion {
+            id: get("id"),
+            status: get("status"),
+            url,
+            min_version: get("min_version"),
+            version: get("version"),
+        }
+    }
+
+    pub fn number(&self) -> Option<(u64, u64)> {
+        parse_version(&self.id)
+    }
+
+    pub fn is_usable(&self) -> bool {
+        match self.status.to_lowercase().as_ref() {
+            "deprecated" | "experimental" => false,
+            _ => true,
+        }
+    }
+
+    pub fn microversion_range(&self) -> Option<((u64, u64), (u64, u64))> {
+        Some((parse_microversion(&self.min_version)?, parse_microversion(&self.version)?))
+    }
+
+    pub fn path_segment(&self) -> String {
+        // the self link knows the real path, 'v2' for glance where the id is 'v2.9'
+        if let Ok(x) = Url::parse(&self.url) {
+            if let Some(y) = x.path_segments().and_then(|y| y.filter(|z| *z != "").last()) {
+                if parse_version(y).is_some() {
+                    return y.to_string();
+                }
+            }
+        }
+        self.id.clone()
+    }
+}
+
+pub fn parse_version(version: &str) -> Option<(u64, u64)> {
+    if !version.starts_with('v') {
+        return None;
+    }
+    let mut parts = version[1..].split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = match parts.next() {
+        Some(x) => x.parse().ok()?,
+        None => 0,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((major, minor))
+}
+
+pub fn parse_microversion(version: &str) -> Option<(u64, u64)> {
+    let mut parts = version.trim().split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((major, minor))
+}
+
+fn format_microversion(version: (u64, u64)) -> String {
+    format!("{}.{}", version.0, version.1)
+}
+
+pub fn negotiate_microversion(
+    requested: Option<&str>,
+    required: Option<(&str, (u64, u64))>,
+    server: Option<&ServiceVersion>,
+) -> Result<Option<String>, OpenstackError> {
+    // without a requested version the lowest version needed by the parameters is used,
+    // a plain major version such as '2' leaves the microversion to the server
+    let range = server.and_then(|x| x.microversion_range());
+    let requested = requested.map(|x| x.trim().to_lowercase()).unwrap_or_default();
+    let chosen = match requested.as_ref() {
+        "latest" => match range {
+            Some((_min, max)) => Some(max),
+            None => {
+                if let Some((name, version)) = required {
+                    debug!("cannot verify that '{}' is usable, it requires {}", name, format_microversion(version));
+                }
+                return Ok(Some("latest".to_string()));
+            }
+        },
+        x if x.contains('.') => match parse_microversion(x) {
+            Some(y) => Some(y),
+            None => return Err(OpenstackError::config(&format!("'{}' is not a valid microversion", x))),
+        },
+        _ => None,
+    };
+    let chosen = match (chosen, required) {
+        (Some(x), Some((name, version))) if x < version => {
+            return Err(OpenstackError::config(&format!(
+                "'{}' requires microversion {} or higher, but {} is used",
+                name,
+                format_microversion(version),
+                format_microversion(x)
+            )))
+        }
+        (Some(x), _) => x,
+        (None, Some((_name, version))) => version,
+        (None, None) => return Ok(None),
+    };
+    if let Some((min, max)) = range {
+        if (chosen < min) | (chosen > max) {
+            return Err(OpenstackError::config(&format!(
+                "microversion {} is not supported, the service supports {} to {}",
+                format_microversion(chosen),
+                format_microversion(min),
+                format_microversion(max)
+            )));
+        }
+    }
+    Ok(Some(format_microversion(chosen)))
+}
+
+pub fn version_root(endpoint: &str) -> String {
+    // strips the project id from urls like 'https://volume.example.com/v3/1234'
+    let mut url = match Url::parse(endpoint) {
+        Ok(x) => x,
+        Err(_e) => return add_slash(endpoint),
+    };
+    let segments: Vec<String> = match url.path_segments() {
+        Some(x) => x.map(|y| y.to_string()).collect(),
+        None => return add_slash(endpoint),
+    };
+    match segments.iter().position(|x| parse_version(x).is_some()) {
+        Some(x) => {
+            url.set_path(&format!("{}/", segments[..x + 1].join("/")));
+            url.to_string()
+        }
+        None => add_slash(endpoint),
+    }
+}
+
+pub fn parse_versions(data: &serde_json::Value) -> Vec<ServiceVersion> {
+    // keystone nests the list in 'values', a versioned url returns a single 'version'
+    let items = if let Some(x) = data["versions"].as_array() {
+        x.clone()
+    } else if let Some(x) = data["versions"]["values"].as_array() {
+        x.clone()
+    } else if data["version"].is_object() {
+        vec![data["version"].clone()]
+    } else {
+        vec![]
+    };
+    items.iter().map(ServiceVersion::from_value).collect()
+}
+
+pub fn pick_version(versions: &[ServiceVersion], supported: &[u64]) -> Option<ServiceVersion> {
+    versions
+        .iter()
+        .filter(|x| x.is_usable())
+        .filter_map(|x| x.number().map(|y| (y, x)))
+        .filter(|(y, _x)| supported.contains(&y.0))
+        .max_by_key(|(y, _x)| *y)
+        .map(|(_y, x)| x.clone())
+}
+
+pub fn endpoint_has_version(endpoint: &str) -> bool {
+    match Url::parse(endpoint) {
+        Ok(x) => x
+            .path_segments()
+            .map(|mut y| y.any(|z| parse_version(z).is_some()))
+            .unwrap_or(false),
+        Err(_e) => false,
+    }
+}
+
+pub fn versioned_endpoint(endpoint: &str, segment: &str) -> String {
+    // catalogs that already include a version are used as is
+    if endpoint_has_version(endpoint) | (segment == "") {
+        return add_slash(endpoint);
+    }
+    format!("{}{}/", add_slash(endpoint), segment)
+}
+
+#[test]
+fn test_parse_versions_picks_newest_supported() {
+    let data = json!({
+        "versions": [
+            {"id": "v2.9", "status": "CURRENT", "links": [{"rel": "self", "href": "http://image.example.com/v2/"}]},
+            {"id": "v2.3", "status": "SUPPORTED", "links": [{"rel": "self", "href": "http://image.example.com/v2/"}]},
+            {"id": "v3.0", "status": "EXPERIMENTAL", "links": [{"rel": "self", "href": "http://image.example.com/v3/"}]},
+            {"id": "v1.1", "status": "DEPRECATED", "links": [{"rel": "self", "href": "http://image.example.com/v1/"}]}
+        ]
+    });
+    let versions = parse_versions(&data);
+    let picked = pick_version(&versions, &[2, 3]).unwrap();
+
+    assert_eq!(picked.id, "v2.9");
+    assert_eq!(picked.path_segment(), "v2");
+    assert!(pick_version(&versions, &[1]).is_none());
+}
+
+#[test]
+fn test_parse_versions_reads_keystone_and_single_version_documents() {
+    let keystone = json!({
+        "versions": {
+            "values": [
+                {"id": "v3.14", "status": "stable", "links": [{"rel": "self", "href": "https://identity.example.com/v3/"}]}
+            ]
+        }
+    });
+    assert_eq!(parse_versions(&keystone)[0].path_segment(), "v3");
+
+    let compute = json!({
+        "version": {"id": "v2.1", "status": "CURRENT", "version": "2.90", "min_version": "2.1", "links": []}
+    });
+    let versions = parse_versions(&compute);
+    assert_eq!(versions[0].version, "2.90");
+    assert_eq!(versions[0].path_segment(), "v2.1");
+}
+
+#[test]
+fn test_negotiate_microversion() {
+    let server = ServiceVersion {
+        id: "v2.1".to_string(),
+        min_version: "2.1".to_string(),
+        version: "2.79".to_string(),
+        ..Default::default()
+    };
+    let negotiate = |requested, required| negotiate_microversion(requested, required, Some(&server));
+
+    assert_eq!(negotiate(None, None).unwrap(), None);
+    assert_eq!(negotiate(Some("2"), None).unwrap(), None);
+    assert_eq!(negotiate(Some("2.53"), None).unwrap(), Some("2.53".to_string()));
+    assert_eq!(negotiate(Some("latest"), None).unwrap(), Some("2.79".to_string()));
+    assert_eq!(negotiate(None, Some(("tags", (2, 52)))).unwrap(), Some("2.52".to_string()));
+    assert!(negotiate(Some("2.80"), None).is_err());
+    assert!(negotiate(Some("2.26"), Some(("tags", (2, 52)))).is_err());
+    assert!(negotiate(Some("2.x"), None).is_err());
+    assert_eq!(
+        negotiate_microversion(Some("latest"), None, None).unwrap(),
+        Some("latest".to_string())
+    );
+}
+
+#[test]
+fn test_version_root() {
+    assert_eq!(version_root("https://volume.example.com/v3/1234"), "https://volume.example.com/v3/");
+    assert_eq!(version_root("https://compute.example.com/compute/v2.1/"), "https://compute.example.com/compute/v2.1/");
+    assert_eq!(version_root("https://share.example.com"), "https://share.example.com/");
+}
+
+#[test]
+fn test_versioned_endpoint() {
+    assert_eq!(versioned_endpoint("https://network.example.com", "v2.0"), "https://network.example.com/v2.0/");
+    assert_eq!(versioned_endpoint("https://example.com/image/", "v2"), "https://example.com/image/v2/");
+    assert_eq!(
+        versioned_endpoint("https://volume.example.com/v3/1234", "v3"),
+        "https://volume.example.com/v3/1234/"
+    );
+    assert_eq!(versioned_endpoint("https://example.com/image", ""), "https://example.com/image/");
+}
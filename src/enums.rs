@@ -96,6 +96,7 @@ impl<'a> From<&'a str> for OSResourceType{
             "volume" => OSResourceType::Volume,
             "volumev2" => OSResourceType::Volume,
             "volumev3" => OSResourceType::Volume,
+            "block-storage" => OSResourceType::Volume,
             "identity" => OSResourceType::Identity,
             "network" => OSResourceType::Networking,
             "image" => OSResourceType::Images,
@@ -1,33 +1,167 @@
 use std::error::Error;
 
-#[derive(Debug, Clone)]
-pub struct OpenstackError {
-    pub details: String,
+use client::Response;
+#[cfg(test)]
+use headers::HeaderMap;
+
+#[derive(Debug)]
+pub enum OpenstackError {
+    // invalid clouds.yaml, arguments or other user input
+    Config(String),
+    // keystone did not hand out a token
+    Auth(String),
+    Http(HttpError),
+    // the request did not get a response
+    Transport(curl::Error),
+    Io(std::io::Error),
+    Serialization(Box<dyn Error + Send + Sync>),
+    Other(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpError {
+    pub status: u32,
+    pub request_id: Option<String>,
+    pub body: serde_json::Value,
+}
+
+impl HttpError {
+    pub fn from_response(response: &Response) -> Self {
+        HttpError {
+            status: response.status(),
+            request_id: response.request_id(),
+            body: response.response(),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        // errors look like {"itemNotFound": {"message": "..."}} or {"error": {"message": "..."}}
+        if let Some(x) = find_message(&self.body) {
+            return x;
+        }
+        match &self.body {
+            serde_json::Value::String(x) if x.trim() != "" => x.trim().to_string(),
+            serde_json::Value::Null => String::from("no response body"),
+            x => x.to_string(),
+        }
+    }
+}
+
+fn find_message(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::Object(x) => {
+            for key in ["message", "faultstring", "title"].iter() {
+                if let Some(y) = x.get(*key).and_then(|z| z.as_str()) {
+                    return Some(y.to_string());
+                }
+            }
+            x.values().filter_map(find_message).next()
+        }
+        _ => None,
+    }
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "HTTP {}: {}", self.status, self.message())?;
+        if let Some(x) = &self.request_id {
+            write!(f, " (request id {})", x)?;
+        }
+        Ok(())
+    }
 }
 
 impl std::fmt::Display for OpenstackError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", &self.details)
+        match self {
+            OpenstackError::Config(x) => write!(f, "{}", x),
+            OpenstackError::Auth(x) => write!(f, "authentication failed: {}", x),
+            OpenstackError::Http(x) => write!(f, "{}", x),
+            OpenstackError::Transport(x) => write!(f, "{}", x),
+            OpenstackError::Io(x) => write!(f, "{}", x),
+            OpenstackError::Serialization(x) => write!(f, "{}", x),
+            OpenstackError::Other(x) => write!(f, "{}", x),
+        }
     }
 }
 
 impl OpenstackError {
     pub fn new(msg: &str) -> OpenstackError {
-        OpenstackError {
-            details: msg.to_string(),
+        OpenstackError::Other(msg.to_string())
+    }
+
+    pub fn config(msg: &str) -> OpenstackError {
+        OpenstackError::Config(msg.to_string())
+    }
+
+    pub fn auth(msg: &str) -> OpenstackError {
+        OpenstackError::Auth(msg.to_string())
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            OpenstackError::Config(_x) => "config",
+            OpenstackError::Auth(_x) => "auth",
+            OpenstackError::Http(_x) => "http",
+            OpenstackError::Transport(_x) => "transport",
+            OpenstackError::Io(_x) => "io",
+            OpenstackError::Serialization(_x) => "serialization",
+            OpenstackError::Other(_x) => "other",
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut error = json!({
+            "kind": self.kind(),
+            "message": self.to_string(),
+            "exit_code": self.exit_code(),
+        });
+        if let OpenstackError::Http(x) = self {
+            error["status"] = x.status.into();
+            error["request_id"] = x.request_id.clone().into();
+            error["body"] = x.body.clone();
+        }
+        json!({ "error": error })
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        // a rejected token is an auth failure, whichever service rejects it
+        match self {
+            OpenstackError::Other(_x) => 1,
+            OpenstackError::Config(_x) => 2,
+            OpenstackError::Auth(_x) => 3,
+            OpenstackError::Http(x) => match x.status {
+                401 | 403 => 3,
+                404 => 4,
+                _ => 5,
+            },
+            OpenstackError::Transport(_x) => 6,
+            OpenstackError::Io(_x) => 7,
+            OpenstackError::Serialization(_x) => 8,
         }
     }
 }
 
 impl std::error::Error for OpenstackError {
-    fn description(&self) -> &str {
-        &self.details
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            OpenstackError::Transport(x) => Some(x),
+            OpenstackError::Io(x) => Some(x),
+            OpenstackError::Serialization(x) => Some(x.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<HttpError> for OpenstackError {
+    fn from(err: HttpError) -> Self {
+        OpenstackError::Http(err)
     }
 }
 
 impl From<curl::Error> for OpenstackError {
     fn from(err: curl::Error) -> Self {
-        OpenstackError::new(err.description())
+        OpenstackError::Transport(err)
     }
 }
 
@@ -51,12 +185,82 @@ impl From<curl::MultiError> for OpenstackError {
 
 impl From<std::io::Error> for OpenstackError {
     fn from(err: std::io::Error) -> Self {
-        OpenstackError::new(err.description())
+        OpenstackError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for OpenstackError {
+    fn from(err: serde_json::Error) -> Self {
+        OpenstackError::Serialization(Box::new(err))
+    }
+}
+
+impl From<serde_yaml::Error> for OpenstackError {
+    fn from(err: serde_yaml::Error) -> Self {
+        OpenstackError::Serialization(Box::new(err))
     }
 }
 
 impl From<handlebars::TemplateRenderError> for OpenstackError{
     fn from(err: handlebars::TemplateRenderError) -> Self {
-        OpenstackError::new(err.description())
+        OpenstackError::new(&format!("{}", err))
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_http_error_message_and_exit_code() {
+    let response = Response(
+        json!({"itemNotFound": {"code": 404, "message": "Instance 1234 could not be found."}}),
+        404,
+        HeaderMap::from_lines(&["x-compute-request-id: req-abc\r\n"]),
+    );
+    let error = OpenstackError::from(HttpError::from_response(&response));
+
+    assert_eq!(
+        format!("{}", error),
+        "HTTP 404: Instance 1234 could not be found. (request id req-abc)"
+    );
+    assert_eq!(error.exit_code(), 4);
+
+    let unauthorized = HttpError {
+        status: 401,
+        request_id: None,
+        body: json!({"error": {"code": 401, "message": "The request you have made requires authentication.", "title": "Unauthorized"}}),
+    };
+    assert_eq!(unauthorized.message(), "The request you have made requires authentication.");
+    assert_eq!(OpenstackError::from(unauthorized).exit_code(), 3);
+}
+
+#[test]
+fn test_error_to_json() {
+    let error = OpenstackError::from(HttpError {
+        status: 409,
+        request_id: Some("req-1".to_string()),
+        body: json!({"conflictingRequest": {"code": 409, "message": "in use"}}),
+    });
+    assert_eq!(
+        error.to_json(),
+        json!({"error": {
+            "kind": "http",
+            "message": "HTTP 409: in use (request id req-1)",
+            "exit_code": 5,
+            "status": 409,
+            "request_id": "req-1",
+            "body": {"conflictingRequest": {"code": 409, "message": "in use"}}
+        }})
+    );
+    assert_eq!(
+        OpenstackError::config("bad").to_json(),
+        json!({"error": {"kind": "config", "message": "bad", "exit_code": 2}})
+    );
+}
+
+#[test]
+fn test_error_source_is_chained() {
+    let io = std::io::Error::new(std::io::ErrorKind::NotFound, "missing");
+    let error = OpenstackError::from(io);
+    assert_eq!(error.exit_code(), 7);
+    assert_eq!(format!("{}", error.source().unwrap()), "missing");
+    assert!(OpenstackError::config("bad").source().is_none());
+    assert_ne!(OpenstackError::config("bad").exit_code(), OpenstackError::auth("bad").exit_code());
+}
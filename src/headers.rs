@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct HeaderMap {
+    // in the order they were received, names keep their original case
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        HeaderMap::default()
+    }
+
+    #[cfg(test)]
+    pub fn from_lines(lines: &[&str]) -> Self {
+        let mut headers = HeaderMap::new();
+        for line in lines {
+            headers.push_line(line.as_bytes());
+        }
+        headers
+    }
+
+    pub fn from_json(value: &serde_json::Value) -> Self {
+        // the reverse of 'to_json', lists become one header per value
+        let mut headers = HeaderMap::new();
+        if let Some(x) = value.as_object() {
+            for (k, v) in x {
+                match v {
+                    serde_json::Value::Array(y) => {
+                        for z in y {
+                            headers.append(k, z.as_str().unwrap_or_default());
+                        }
+                    }
+                    serde_json::Value::String(y) => headers.append(k, y),
+                    y => headers.append(k, &y.to_string()),
+                }
+            }
+        }
+        headers
+    }
+
+    pub fn push_line(&mut self, line: &[u8]) {
+        // gets every raw line from curl's header callback, including the status lines
+        let line = String::from_utf8_lossy(line);
+        if line.starts_with("HTTP/") {
+            // a redirect or '100 Continue' came first, only keep the headers of the last response
+            self.entries.clear();
+            return;
+        }
+        if line.starts_with(' ') | line.starts_with('\t') {
+            // folded header, continues the previous value
+            if let Some(x) = self.entries.last_mut() {
+                x.1 = format!("{} {}", x.1, line.trim()).trim().to_string();
+            }
+            return;
+        }
+        let line = line.trim_end_matches(|x| (x == '\r') | (x == '\n'));
+        let mut split = line.splitn(2, ':');
+        match (split.next(), split.next()) {
+            (Some(k), Some(v)) if k.trim() != "" => self.append(k.trim(), v.trim()),
+            _ => (),
+        }
+    }
+
+    pub fn append(&mut self, key: &str, value: &str) {
+        self.entries.push((key.to_string(), value.to_string()));
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_all(key).into_iter().next()
+    }
+
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(k, _v)| k.eq_ignore_ascii_case(key))
+            .map(|(_k, v)| v.as_str())
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        // lowercase names, headers that are sent more than once become a list
+        let mut map: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for (k, v) in self.iter() {
+            map.entry(k.to_lowercase()).or_insert_with(Vec::new).push(v);
+        }
+        map.into_iter()
+            .map(|(k, mut v)| match v.len() {
+                1 => (k, v.remove(0).into()),
+                _ => (k, v.into()),
+            })
+            .collect::<serde_json::Map<String, serde_json::Value>>()
+            .into()
+    }
+}
+
+#[test]
+fn test_header_values_keep_colons() {
+    let headers = HeaderMap::from_lines(&[
+        "HTTP/1.1 201 Created\r\n",
+        "Location: https://example.com:8774/v2.1/servers/1234\r\n",
+        "Date: Sun, 18 Oct 2026 09:12:45 GMT\r\n",
+        "X-Empty:\r\n",
+        "\r\n",
+    ]);
+    assert_eq!(headers.get("location"), Some("https://example.com:8774/v2.1/servers/1234"));
+    assert_eq!(headers.get("DATE"), Some("Sun, 18 Oct 2026 09:12:45 GMT"));
+    assert_eq!(headers.get("x-empty"), Some(""));
+    assert_eq!(headers.get("HTTP/1.1 201 Created"), None);
+    assert_eq!(headers.iter().count(), 3);
+}
+
+#[test]
+fn test_headers_are_case_insensitive_and_multi_valued() {
+    let headers = HeaderMap::from_lines(&[
+        "HTTP/1.1 200 OK\r\n",
+        "Set-Cookie: a=1\r\n",
+        "set-cookie: b=2\r\n",
+        "X-Subject-Token: gAAAA\r\n",
+        "Warning: 299 - \"first part\r\n",
+        "\tsecond part\"\r\n",
+    ]);
+    assert_eq!(headers.get_all("SET-COOKIE"), vec!["a=1", "b=2"]);
+    assert_eq!(headers.get("x-subject-token"), Some("gAAAA"));
+    assert_eq!(headers.get("warning"), Some("299 - \"first part second part\""));
+    assert_eq!(
+        headers.to_json(),
+        json!({"set-cookie": ["a=1", "b=2"], "x-subject-token": "gAAAA", "warning": "299 - \"first part second part\""})
+    );
+}
+
+#[test]
+fn test_headers_from_json() {
+    let headers = HeaderMap::from_json(&json!({"set-cookie": ["a=1", "b=2"], "content-length": 12}));
+    assert_eq!(headers.get_all("Set-Cookie"), vec!["a=1", "b=2"]);
+    assert_eq!(headers.get("content-length"), Some("12"));
+    assert_eq!(HeaderMap::from_json(&headers.to_json()), headers);
+}
+
+#[test]
+fn test_only_the_last_response_is_kept() {
+    let headers = HeaderMap::from_lines(&[
+        "HTTP/1.1 100 Continue\r\n",
+        "\r\n",
+        "HTTP/1.1 302 Found\r\n",
+        "Location: https://example.com/other\r\n",
+        "\r\n",
+        "HTTP/1.1 200 OK\r\n",
+        "Content-Type: application/json\r\n",
+        "\r\n",
+    ]);
+    assert_eq!(headers.get("location"), None);
+    assert_eq!(headers.get("content-type"), Some("application/json"));
+}
@@ -29,14 +29,21 @@ extern crate strum;
 extern crate strum_macros;
 extern crate yaml_rust;
 extern crate uuid;
+extern crate rand;
 extern crate memmap;
 extern crate sha2;
+extern crate chacha20poly1305;
+extern crate pbkdf2;
+extern crate hmac;
+#[cfg(feature = "keyring")]
+extern crate keyring;
 
 #[macro_use]
 mod macros;
 
 mod error;
 mod client;
+mod headers;
 mod enums;
 mod utils;
 mod openstack_connection;
@@ -44,6 +51,14 @@ mod structs;
 mod objectstore;
 mod traits;
 mod config;
+mod cache;
+mod catalog;
+mod discovery;
+mod pagination;
+mod query;
+mod recording;
+mod trace;
+mod secrets;
 
 use std::collections::HashMap;
 use std::str::FromStr;
@@ -57,9 +72,14 @@ use enums::OSOperation;
 use structs::{Command, Resource};
 use config::{OpenstackInfoMap};
 use openstack_connection::{Openstack};
-use utils::{get_first_value_from_hashmap_with_vec, print_value, make_args_from_arg_matches};
+use utils::{get_first_value_from_hashmap_with_vec, print_output, make_args_from_arg_matches, OutputOptions, DEFAULT_SHELL_PREFIX, OUTPUT_FORMATS};
 use error::OpenstackError;
 
+const MICROVERSION_ARGS: [(&str, &str); 3] = [
+    ("os-compute-api-version", "compute microversion to use, such as '2.53' or 'latest'"),
+    ("os-volume-api-version", "volume microversion to use, such as '3.59' or 'latest'"),
+    ("os-shared-file-system-api-version", "shared file system microversion to use, such as '2.46' or 'latest'"),
+];
 
 fn main() {
     let mut os_cloud: String = "".to_string();
@@ -75,6 +95,10 @@ fn main() {
 
     let format = matches.value_of("format").expect("this has a default");
     debug!("set output format to {}", format);
+    let output_options = match OutputOptions::from_matches(&matches){
+        Ok(x) => x,
+        Err(e) => return print_error(OpenstackError::config(&e), format)
+    };
 
     let (command_input, command_sub) = match matches.subcommand(){
         (x, Some(y)) => (x, y),
@@ -91,27 +115,112 @@ fn main() {
         return ();
     }
 
+    if let Some(sub_m) = matches.subcommand_matches("cache") {
+        let outcome = match run_cache_command(sub_m){
+            Ok(x) => x,
+            Err(e) => return print_error(e, format)
+        };
+        return print_value(&outcome, format, &output_options);
+    }
+
     let matches_options = make_args_from_arg_matches(&matches);
     let command_options = make_args_from_arg_matches(command_sub);
     // let os_command = OSOperation::from(command_input);
 
     if let Some(x) = get_first_value_from_hashmap_with_vec(&matches_options, "os-cloud"){
-        os_cloud = x.to_string();
+        os_cloud = x.as_str().map(|y| y.to_string()).unwrap_or_else(|| x.to_string());
     };
 
     let os_config_env = OpenstackInfoMap::from_env(os_cloud.clone());
     debug!("config from env {:?}", os_config_env);
     let mut os_config = match OpenstackInfoMap::from_clouds_yaml(os_cloud.clone()){
         Ok(x) => x,
-        Err(_e) => OpenstackInfoMap::default()
+        // a cloud that was asked for has to exist, otherwise everything comes from the environment
+        Err(e) => match os_cloud.as_str(){
+            "" => OpenstackInfoMap::default(),
+            _ => return print_error(e, format)
+        }
     };
     debug!("config from yaml {:?}", os_config);
 
-    os_config.apply(&os_config_env)
-      .add_password_if_not_existing().unwrap();
+    os_config.apply(&os_config_env);
+    if matches.is_present("api-retry-count") {
+        os_config.api_retry_count = Some(value_t_or_exit!(matches, "api-retry-count", u32));
+    }
+    if matches.is_present("connect-retries") {
+        os_config.connect_retries = Some(value_t_or_exit!(matches, "connect-retries", u32));
+    }
+    if let Some(x) = matches.value_of("os-cacert") {
+        os_config.cacert = Some(x.to_string());
+    }
+    if matches.is_present("insecure") {
+        os_config.verify = Some(false);
+    }
+    if matches.is_present("connect-timeout") {
+        os_config.connect_timeout = Some(value_t_or_exit!(matches, "connect-timeout", u64));
+    }
+    if matches.is_present("timeout") {
+        os_config.api_timeout = Some(value_t_or_exit!(matches, "timeout", u64));
+    }
+    if let Some(x) = matches.value_of("proxy") {
+        os_config.proxy = Some(x.to_string());
+    }
+    if let Some(x) = matches.value_of("no-proxy") {
+        os_config.no_proxy = Some(x.to_string());
+    }
+    if matches.is_present("debug-http") {
+        os_config.debug_http = true;
+    }
+    if let Some(x) = matches.value_of("record") {
+        if let Err(e) = recording::start_recording(x){
+            return print_error(e, format)
+        };
+        os_config.record = Some(x.to_string());
+    }
+    if let Some(x) = matches.value_of("replay") {
+        os_config.replay = Some(x.to_string());
+    }
+    for (name, _help) in MICROVERSION_ARGS.iter() {
+        if let Some(x) = matches.value_of(name) {
+            os_config.set_service_option(&name[3..].replace("-", "_"), x.to_string());
+        }
+    }
+    if let Some(x) = matches.value_of("microversion") {
+        // the old option of the servers resource, kept so existing scripts keep working
+        eprintln!("warning: --microversion is deprecated, use --os-compute-api-version");
+        os_config.set_service_option("compute_api_version", x.to_string());
+    }
+
+    if command_input == "logout" {
+        let outcome = match logout(&os_config){
+            Ok(x) => x,
+            Err(e) => return print_error(e, format)
+        };
+        return print_value(&outcome, format, &output_options);
+    }
+
+    if command_input != "login" {
+        match secrets::default_store() {
+            Ok(store) => if let Err(e) = os_config.add_password_from_store(&*store) {
+                debug!("unable to read the secret store: {}", e)
+            },
+            Err(e) => debug!("{}", e)
+        };
+    }
+    if let Err(e) = os_config.add_password_if_not_existing(){
+        return print_error(e, format)
+    };
 
     debug!("config combined {:?}", os_config);
 
+    if command_input == "login" {
+        let outcome = match login(os_config){
+            Ok(x) => x,
+            Err(e) => return print_error(e, format)
+        };
+        return print_value(&outcome, format, &output_options);
+    }
+
     let mut new_os = match matches_options.get("use-cache").is_some(){
         true => {
             debug!("trying to use cache");
@@ -140,7 +249,7 @@ fn main() {
 
         let resource_type = match new_os.resources.get_resource_type(os_type.into()){
             Ok(x) => x,
-            Err(e) => {println!("{{\"error\": \"{}\"}}", e); return ()}
+            Err(e) => return print_error(e, format)
         };
         let tmp_resource: Resource = serde_json::from_value(json!({
             "name": "tmp",
@@ -149,7 +258,7 @@ fn main() {
         })).unwrap();
         let _http_method = match http::Method::from_str(method){
             Ok(x) => x,
-            Err(e) => {println!("{{\"error\": \"{}\"}}", e); return ()}
+            Err(e) => return print_error(OpenstackError::config(&format!("{}", e)), format)
         };
         let command: Command = serde_json::from_value(
             json!({
@@ -165,7 +274,7 @@ fn main() {
             for header in x{
                 let header_parts: Vec<&str> = header.split(":").collect();
                 if header_parts.len() != 2 {
-                    return print_value(&json!({"error": "invalid header"}), format);
+                    return print_error(OpenstackError::config(&format!("invalid header '{}'", header)), format);
                 }
                 new_os.connection.client.set_header(header_parts[0], header_parts[1])
             }
@@ -179,30 +288,32 @@ fn main() {
             let q: String = x.collect::<Vec<&str>>().join(" ");
             let v: serde_json::Value = match serde_json::from_str(&q){
                 Ok(x) => x,
-                Err(_e) => {
-                    let error = json!({
-                        "error": "given body is invalid json"
-                    });
-                    print_value(&error, format);
-                    return ()
-                }
+                Err(e) => return print_error(OpenstackError::config(&format!("given body is invalid json: {}", e)), format)
             };
             new_os.connection.client.set_json(v);
         };
 
         if command_options.get("dry-run").is_some(){
             println!("{:?} {:?}\nHeaders: {:?}", new_os.connection.client.method, new_os.connection.client.url, new_os.connection.client.headers);
-            print_value(&new_os.connection.client.json, format);
+            print_value(&new_os.connection.client.json, format, &output_options);
             return ();
         }
         // let mut lbab = req.send().expect("request failed");
-        let mut response = new_os.connection.client.perform().expect("request failed");
-        let outcome = match Openstack::handle_response(&mut response){
+        let mut response = match new_os.connection.perform(){
+            Ok(x) => x,
+            Err(e) => return print_error(e, format)
+        };
+        let mut outcome = match Openstack::handle_response(&mut response).and_then(|x| output_options.apply(&x).map_err(|e| OpenstackError::config(&e))){
             Ok(x) => x,
-            Err(e) => {println!("{}", e); return}
+            Err(e) => return print_error(e, format)
         };
+        if matches.is_present("include-headers"){
+            outcome = response.with_metadata(outcome);
+        }
 
-        print_value(&outcome, format);
+        if let Err(e) = print_output(&outcome, format, &output_options){
+            return print_error(OpenstackError::config(&e), format)
+        };
         return ();
     }
 
@@ -225,47 +336,67 @@ fn main() {
             let return_object = json!({
                 "token": token
             });
-            print_value(&return_object, format);
+            print_value(&return_object, format, &output_options);
             return;
         }
         else{
-            let return_object = json!({
-                "error": "token is not available"
-            });
-            print_value(&return_object, format);
-            return;
+            return print_error(OpenstackError::auth("token is not available"), format);
         }
     }
 
     if command_input == "list" && resource_input == "endpoints"{
-        if let Some(endpoints) = new_os.connection.endpoints{
+        if let Some(catalog) = new_os.connection.catalog{
             let return_object = json!({
-                "endpoints": endpoints
+                "catalog": catalog.services
             });
-            print_value(&return_object, format);
+            print_value(&return_object, format, &output_options);
             return;
         }
-        else{
+        // caches from older versions only have the selected endpoints
+        else if let Some(endpoints) = new_os.connection.endpoints{
             let return_object = json!({
-                "error": "endpoints are not available"
+                "endpoints": endpoints
             });
-            print_value(&return_object, format);
+            print_value(&return_object, format, &output_options);
             return;
         }
+        else{
+            return print_error(OpenstackError::config("endpoints are not available"), format);
+        }
     }
 
     if !new_os.is_resource_available(resource_input.into()){
-        print_value(&json!({"error": format!("endpoint for resource '{}' is not available", resource_input)}), format);
-        return ()
+        return print_error(OpenstackError::config(&format!("endpoint for resource '{}' is not available", resource_input)), format);
     };
 
-    let outcome = match new_os.act(command_input.to_string(), resource_input.to_string(), &command_options, &resource_options){
+    // with a page size every page is printed as soon as it arrives
+    let mut print_page = |page: &serde_json::Value| print_value(page, format, &output_options);
+    // sorting and queries need every item, so those pages are merged before anything is printed
+    let on_page: Option<&mut dyn FnMut(&serde_json::Value)> = match output_options.needs_whole_list(){
+        true => None,
+        false => Some(&mut print_page)
+    };
+    let mut outcome = match new_os.act_paged(command_input.to_string(), resource_input.to_string(), &command_options, &resource_options, on_page){
         Ok(x) => x,
-        Err(e) => return print_value(&json!({"error": format!("{}", e)}), format)
+        Err(e) => return print_error(e, format)
     };
+    if outcome.is_null() && command_options.get("page-size").is_some() {
+        return;
+    }
+    outcome = match output_options.apply(&outcome){
+        Ok(x) => x,
+        Err(e) => return print_error(OpenstackError::config(&e), format)
+    };
+    if matches.is_present("include-headers"){
+        if let Some(x) = &new_os.last_response{
+            outcome = x.with_metadata(outcome);
+        }
+    }
 
     // println!("{}", serde_json::to_string_pretty(&outcome).unwrap());
-    print_value(&outcome, format);
+    if let Err(e) = print_output(&outcome, format, &output_options){
+        print_error(OpenstackError::config(&e), format)
+    };
 
 }
 
@@ -275,12 +406,56 @@ fn prepare_app<'a>(app: App<'a, 'a>) -> App<'a, 'a>{
         .arg(
             Arg::with_name("format")
                 .short("f")
+                .long("format")
                 .help("formats output with this format")
                 .takes_value(true)
                 .global(true)
-                .possible_values(&["json", "csv", "table"])
+                .possible_values(&OUTPUT_FORMATS)
                 .default_value("json")
         )
+        .arg(Arg::with_name("columns")
+                .help("only outputs these comma separated fields, nested fields are selected like 'addresses.private.0.addr'")
+                .long("columns")
+                .takes_value(true)
+                .value_name("COLUMNS")
+                .global(true)
+        )
+        .arg(Arg::with_name("sort-by")
+                .help("sorts the items in a list by this field")
+                .long("sort-by")
+                .takes_value(true)
+                .value_name("FIELD")
+                .global(true)
+        )
+        .arg(Arg::with_name("reverse")
+                .help("reverses the order of the items in a list")
+                .long("reverse")
+                .global(true)
+        )
+        .arg(Arg::with_name("filter")
+                .help("only outputs the items in a list where the field has this value, can be given more than once")
+                .long("filter")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("KEY=VALUE")
+                .global(true)
+        )
+        .arg(Arg::with_name("prefix")
+                .help("is put before every variable name of the shell format")
+                .long("prefix")
+                .takes_value(true)
+                .value_name("PREFIX")
+                .default_value(DEFAULT_SHELL_PREFIX)
+                .global(true)
+        )
+        .arg(Arg::with_name("query")
+                .help("a JMESPath expression that selects what is outputted, like \"servers[?status=='ACTIVE'].{id: id, name: name}\"")
+                .long("query")
+                .takes_value(true)
+                .value_name("EXPRESSION")
+                .global(true)
+        )
         .arg(Arg::with_name("verbose")
                 .help("increases the verbosity")
                 .short("v")
@@ -291,6 +466,113 @@ fn prepare_app<'a>(app: App<'a, 'a>) -> App<'a, 'a>{
                 .help("try to use cache, but when that fails use normal api calls")
                 .long("use-cache")
         )
+        .arg(Arg::with_name("api-retry-count")
+                .help("number of retries after 429, 502, 503 and 504 responses")
+                .long("api-retry-count")
+                .takes_value(true)
+                .value_name("COUNT")
+        )
+        .arg(Arg::with_name("connect-retries")
+                .help("number of retries after connection failures")
+                .long("connect-retries")
+                .takes_value(true)
+                .value_name("COUNT")
+        )
+        .arg(Arg::with_name("os-cacert")
+                .help("CA bundle to verify the server certificates with")
+                .long("os-cacert")
+                .takes_value(true)
+                .value_name("FILE")
+        )
+        .arg(Arg::with_name("insecure")
+                .help("do not verify the server certificates")
+                .long("insecure")
+        )
+        .arg(Arg::with_name("debug-http")
+                .help("logs every request and response to stderr like 'curl -v', with secrets redacted")
+                .long("debug-http")
+        )
+        .arg(Arg::with_name("record")
+                .help("saves every api call with its response in this json-lines file, secrets are redacted")
+                .long("record")
+                .takes_value(true)
+                .value_name("FILE")
+        )
+        .arg(Arg::with_name("replay")
+                .help("answers api calls from a file made with --record instead of the network")
+                .long("replay")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with("record")
+        )
+        .arg(Arg::with_name("include-headers")
+                .help("outputs the status code, headers and request id together with the response body")
+                .long("include-headers")
+                .visible_alias("verbose-response")
+        )
+        .arg(Arg::with_name("connect-timeout")
+                .help("seconds to wait for a connection, defaults to 30")
+                .long("connect-timeout")
+                .takes_value(true)
+                .value_name("SECONDS")
+        )
+        .arg(Arg::with_name("timeout")
+                .help("seconds an api call may take, 0 waits forever, defaults to 300")
+                .long("timeout")
+                .takes_value(true)
+                .value_name("SECONDS")
+        )
+        .arg(Arg::with_name("proxy")
+                .help("http, https or socks proxy to use, such as 'socks5h://localhost:1080'")
+                .long("proxy")
+                .takes_value(true)
+                .value_name("URL")
+        )
+        .arg(Arg::with_name("no-proxy")
+                .help("comma separated hosts that are not proxied")
+                .long("no-proxy")
+                .takes_value(true)
+                .value_name("HOSTS")
+        )
+        .args(&MICROVERSION_ARGS.iter().map(|(name, help)| {
+            Arg::with_name(name)
+                .long(name)
+                .help(help)
+                .takes_value(true)
+                .value_name("VERSION")
+                .global(true)
+        }).collect::<Vec<Arg>>())
+        .arg(Arg::with_name("microversion")
+                .help("deprecated, use --os-compute-api-version")
+                .long("microversion")
+                .takes_value(true)
+                .value_name("VERSION")
+                .conflicts_with("os-compute-api-version")
+                .global(true)
+        )
+        .subcommand(
+            SubCommand::with_name("login")
+                .about("stores the password of the current cloud in the secret store")
+        )
+        .subcommand(
+            SubCommand::with_name("logout")
+                .about("removes the stored password and cached token of the current cloud")
+        )
+        .subcommand(
+            SubCommand::with_name("cache")
+                .about("inspects and clears the token cache")
+                .subcommand(SubCommand::with_name("list")
+                    .about("lists the cached entries"))
+                .subcommand(SubCommand::with_name("show")
+                    .about("shows a cached entry without its token")
+                    .arg(Arg::with_name("name")
+                        .required(true)
+                        .help("name of the entry, as shown by 'cache list'")))
+                .subcommand(SubCommand::with_name("purge")
+                    .about("removes all cached entries")
+                    .arg(Arg::with_name("name")
+                        .help("only remove this entry")))
+        )
         .subcommand(
             SubCommand::with_name("generate-autocomplete")
                 .about("generates autocompletion scripts")
@@ -300,6 +582,49 @@ fn prepare_app<'a>(app: App<'a, 'a>) -> App<'a, 'a>{
         )
 }
 
+fn login(os_config: OpenstackInfoMap) -> Result<serde_json::Value, OpenstackError>{
+    // only store the password when it is accepted
+    let store = secrets::default_store()?;
+    let key = os_config.secret_key();
+    let password = os_config.auth.password.unsecure().to_string();
+    Openstack::new(os_config)?;
+    store.set(&key, &password)?;
+    Ok(json!({"login": key}))
+}
+
+fn logout(os_config: &OpenstackInfoMap) -> Result<serde_json::Value, OpenstackError>{
+    let store = secrets::default_store()?;
+    let key = os_config.secret_key();
+    store.delete(&key)?;
+    cache::purge_cache(Some(&os_config.auth.create_hash()))?;
+    Ok(json!({"logout": key}))
+}
+
+fn run_cache_command(matches: &clap::ArgMatches) -> Result<serde_json::Value, OpenstackError>{
+    match matches.subcommand(){
+        ("show", Some(x)) => {
+            let name = x.value_of("name").expect("this value is required");
+            let data = cache::read_cache(name)?;
+            let mut value: serde_json::Value = match serde_json::from_slice(&data){
+                Ok(y) => y,
+                Err(e) => return Err(e.into())
+            };
+            if value.get("token").is_some(){
+                value["token"] = json!("<redacted>");
+            }
+            Ok(value)
+        },
+        ("purge", Some(x)) => {
+            let removed = cache::purge_cache(x.value_of("name"))?;
+            Ok(json!({"removed": removed}))
+        },
+        _ => {
+            let entries = cache::list_cache()?;
+            Ok(json!({"cache": entries}))
+        }
+    }
+}
+
 fn set_log_level(matches: &clap::ArgMatches){
     let log_level = match matches.occurrences_of("verbose"){
         0 => log::Level::Error,
@@ -310,7 +635,19 @@ fn set_log_level(matches: &clap::ArgMatches){
     simple_logger::init_with_level(log_level).expect("unable to set logger");
 }
 
+// runs --query, --filter, --sort-by and --columns before the value is printed
+fn print_value(value: &serde_json::Value, format: &str, output_options: &OutputOptions){
+    match output_options.apply(value).and_then(|x| print_output(&x, format, output_options)){
+        Ok(()) => (),
+        Err(e) => print_error(OpenstackError::config(&e), format)
+    }
+}
 
 fn print_error(err: OpenstackError, format: &str){
-    print_value(&json!({"error": format!("{}", err)}), format)
+    // errors never end up between the data on stdout
+    match format{
+        "json" => eprintln!("{}", serde_json::to_string_pretty(&err.to_json()).unwrap()),
+        _ => eprintln!("error: {}", err)
+    };
+    std::process::exit(err.exit_code())
 }
\ No newline at end of file
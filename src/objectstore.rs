@@ -2,31 +2,33 @@ use std::io::{stdout, Read, Write};
 use std::fs::File;
 
 use indicatif::{ProgressBar, ProgressStyle};
-use curl::easy::{Easy, List};
+use curl::easy::List;
 use memmap::MmapOptions;
 use serde_json::Value as JSONValue;
 
 
 use error::OpenstackError;
-use client::Response;
+use client::{ClientOptions, Response};
+use headers::HeaderMap;
 
 pub fn upload_to_object_store(
     file: &mut File,
     object_store_url: &str,
     token: &str,
+    options: &ClientOptions,
 ) -> Result<Response, OpenstackError> {
     let mut headers = List::new();
     headers.append(&format!("X-Auth-Token: {}", token))?;
 
     let mut data = Vec::new();
 
-    let mut easy = Easy::new();
+    let mut easy = options.new_transfer_handle()?;
     easy.url(object_store_url)?;
     easy.upload(true)?;
     easy.http_headers(headers)?;
     easy.progress(true)?;
 
-    let mut remote_headers = vec![];
+    let mut remote_headers = HeaderMap::new();
 
     {
         let progress_bar = make_progress_bar(file.metadata()?.len());
@@ -43,7 +45,7 @@ pub fn upload_to_object_store(
                 Ok(new_data.len())
             })?;
         transfer.header_function(|header| {
-            remote_headers.push(String::from_utf8(header.to_vec()).unwrap_or(String::from(" : ")));
+            remote_headers.push_line(header);
             true
         })?;
         transfer.read_function(|into| Ok(file.read(into).unwrap()))?;
@@ -62,6 +64,7 @@ pub fn download_from_object_store(
     file: &mut File,
     object_store_url: &str,
     token: &str,
+    options: &ClientOptions,
 ) -> Result<Response, OpenstackError> {
     let mut headers = List::new();
     headers.append(&format!("X-Auth-Token: {}", token))?;
@@ -69,13 +72,13 @@ pub fn download_from_object_store(
     // let urlpath = &format!("{}/{}/{}", object_store_url, container, name);
     let urlpath = object_store_url;
 
-    let mut easy = Easy::new();
+    let mut easy = options.new_transfer_handle()?;
     easy.url(urlpath)?;
     easy.get(true)?;
     easy.http_headers(headers)?;
     easy.progress(true)?;
 
-    let mut remote_headers = vec![];
+    let mut remote_headers = HeaderMap::new();
 
     {
         let progress_bar = make_progress_bar(0);
@@ -89,7 +92,7 @@ pub fn download_from_object_store(
         })?;
         transfer.write_function(|data| Ok(file.write(data).unwrap()))?;
         transfer.header_function(|header| {
-            remote_headers.push(String::from_utf8(header.to_vec()).unwrap_or(String::from(" : ")));
+            remote_headers.push_line(header);
             true
         })?;
         transfer.perform()?;
@@ -112,13 +115,14 @@ pub fn upload_to_object_store_dynamic_large_objects(
     token: &str,
     parts: usize,
     skip_first: usize,
+    options: &ClientOptions,
 ) -> Result<Response, OpenstackError> {
     let fileurl = object_store_url;
 
     let mut headers = List::new();
     headers.append(&format!("X-Auth-Token: {}", token))?;
 
-    let mut easy = Easy::new();
+    let mut easy = options.new_transfer_handle()?;
     easy.url(fileurl)?;
     easy.upload(true)?;
     easy.http_headers(headers)?;
@@ -133,7 +137,7 @@ pub fn upload_to_object_store_dynamic_large_objects(
 
         let chuckies = mmap.chunks(amount).skip(skip_first);
         for (index, mut chunk) in chuckies.enumerate() {
-            let response = upload_part(&mut chunk, index, fileurl, token)?;
+            let response = upload_part(&mut chunk, index, fileurl, token, options)?;
 
             if !response.is_success() {
                 success = false;
@@ -145,7 +149,7 @@ pub fn upload_to_object_store_dynamic_large_objects(
         }
     }
     if success == true {
-        set_dynamic_manifest(fileurl, container, name, token)?;
+        set_dynamic_manifest(fileurl, container, name, token, options)?;
     }
 
     match success {
@@ -193,13 +197,14 @@ fn set_dynamic_manifest(
     container: &str,
     filename: &str,
     token: &str,
+    options: &ClientOptions,
 ) -> Result<(String, u32), OpenstackError> {
     let mut headers = List::new();
     headers.append(&format!("X-Auth-Token: {}", token))?;
     headers.append(&format!("X-Object-Manifest: {}/{}/", container, filename))?;
 
     let mut data = Vec::new();
-    let mut easy = Easy::new();
+    let mut easy = options.new_transfer_handle()?;
     easy.url(&fileurl)?;
     easy.upload(true)?;
     easy.http_headers(headers)?;
@@ -222,18 +227,19 @@ fn upload_part(
     index: usize,
     fileurl: &str,
     token: &str,
+    options: &ClientOptions,
 ) -> Result<Response, OpenstackError> {
     let mut headers = List::new();
     headers.append(&format!("X-Auth-Token: {}", token))?;
 
     let mut data = Vec::new();
-    let mut easy = Easy::new();
+    let mut easy = options.new_transfer_handle()?;
     easy.url(&format!("{}/{:08}", fileurl, index))?;
     easy.upload(true)?;
     easy.progress(true)?;
     easy.http_headers(headers)?;
 
-    let mut remote_headers = vec![];
+    let mut remote_headers = HeaderMap::new();
 
     {
         let progress_bar = make_progress_bar(chunk.len() as u64);
@@ -249,7 +255,7 @@ fn upload_part(
             true
         })?;
         transfer.header_function(|header| {
-            remote_headers.push(String::from_utf8(header.to_vec()).unwrap_or(String::from(" : ")));
+            remote_headers.push_line(header);
             true
         })?;
 
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{Error, ErrorKind};
 use std::iter::DoubleEndedIterator;
 use std::str::FromStr;
@@ -7,16 +7,24 @@ use chrono::prelude::*;
 use chrono::Duration;
 
 use enums::OSOperation;
-use structs::{Action, ActionMap, Command, CommandMap, Resource, ResourceMap, ResourceTypeEnum};
+use structs::{Action, ActionMap, Command, CommandMap, Resource, ResourceMap, ResourceType, ResourceTypeEnum};
 use utils::{
     add_slash, remove_slash, get_first_value_from_hashmap_with_vec, hashmap_with_vec_to_json,
     make_hashmaps_from_dot_notation, read_yaml, remove_slash_start,
 };
 use uuid::Uuid;
 
+use catalog::ServiceCatalog;
+use discovery::{
+    endpoint_has_version, negotiate_microversion, parse_microversion, parse_versions,
+    pick_version, version_root, versioned_endpoint, ServiceVersion, VersionSpec, VersionSpecs,
+};
 use config::{OpenstackInfoMap, OpenstackTokenizer};
+use pagination::{
+    merge_pages, next_link, next_marker, resolve_link, set_query, split_page, PageOptions,
+};
 use client::{Client, Response};
-use error::OpenstackError;
+use error::{HttpError, OpenstackError};
 
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -28,8 +36,11 @@ pub struct OpenstackConnection {
     pub token: Option<String>,
     pub token_expiry: Option<String>,
     pub endpoints: Option<HashMap<String, String>>,
+    pub catalog: Option<ServiceCatalog>,
     pub domain_id: Option<String>,
     pub user_id: Option<String>,
+    #[serde(skip)]
+    pub use_cache: bool,
 }
 
 impl OpenstackConnection {
@@ -42,7 +53,9 @@ impl OpenstackConnection {
         //     // .referer(true)
         //     .build()
         //     .unwrap();
-        let client = Client::new();
+        let mut client = Client::new();
+        client.retry = config.retry_policy();
+        client.options = config.client_options();
         let config = OpenstackTokenizer::new(config);
         OpenstackConnection {
             config,
@@ -50,23 +63,29 @@ impl OpenstackConnection {
             token: None,
             token_expiry: None,
             endpoints: None,
+            catalog: None,
             domain_id: None,
             user_id: None,
+            use_cache: false,
         }
     }
 
     pub fn from_cache(config: &OpenstackInfoMap) -> Result<OpenstackConnection, OpenstackError> {
         let config = OpenstackTokenizer::from_cache(config)?;
         debug!("use cache");
-        let client = Client::new();
+        let mut client = Client::new();
+        client.retry = config.config.retry_policy();
+        client.options = config.config.client_options();
         let mut connection = OpenstackConnection {
             config,
             client,
             token: None,
             token_expiry: None,
             endpoints: None,
+            catalog: None,
             domain_id: None,
             user_id: None,
+            use_cache: true,
         };
         connection.copy_from_tokenizer()?;
         Ok(connection)
@@ -82,14 +101,20 @@ impl OpenstackConnection {
 
     pub fn from_cache_or_new_refreshed(config: OpenstackInfoMap) -> Result<OpenstackConnection, OpenstackError> {
         match OpenstackConnection::from_cache(&config) {
-            Ok(x) => return Ok(x),
+            Ok(mut x) => {
+                if x.config.needs_refresh() {
+                    debug!("cached token is about to expire");
+                    x.refresh_token()?;
+                }
+                return Ok(x)
+            },
             Err(e) => debug!("cache failed because {}", e)
         };
 
         debug!("refresh cache");
         let mut connection = OpenstackConnection::new(config);
+        connection.use_cache = true;
         connection.refresh_token()?;
-        connection.to_cache()?;
         Ok(connection)
     }
 
@@ -138,10 +163,15 @@ impl OpenstackConnection {
         self.token = self.config.token.clone();
         match self.token.as_ref(){
             Some(x) => self.client.set_token(x),
-            None => return Err(OpenstackError::new("something went wrong setting the token"))
+            None if self.config.config.auth.uses_no_auth() => (),
+            None => return Err(OpenstackError::auth("something went wrong setting the token"))
         }
         self.token_expiry = self.config.token_expiry.clone();
         self.endpoints = self.config.endpoints.clone();
+        if let Some(x) = self.endpoints.as_mut(){
+            self.config.config.apply_endpoint_overrides(x);
+        }
+        self.catalog = self.config.catalog.clone();
         self.domain_id = self.config.domain_id.clone();
         self.user_id = self.config.user_id.clone();
         Ok(())
@@ -150,8 +180,26 @@ impl OpenstackConnection {
     pub fn refresh_token(&mut self) -> Result<(), OpenstackError> {
         self.config.refresh_token()?;
         self.copy_from_tokenizer()?;
+        if self.use_cache {
+            self.to_cache()?;
+        }
         Ok(())
     }
+
+    pub fn perform(&mut self) -> Result<Response, OpenstackError> {
+        // refreshes the token when it expired and retries once when the api still says 401
+        if self.config.needs_refresh() {
+            debug!("token is about to expire");
+            self.refresh_token()?;
+        }
+        let response = self.client.perform()?;
+        if response.status() != 401 {
+            return Ok(response);
+        }
+        debug!("request unauthorized, retrying with a new token");
+        self.refresh_token()?;
+        self.client.perform()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -160,6 +208,9 @@ pub struct Openstack {
     pub resources: ResourceMap,
     pub actions: ActionMap,
     pub commands: CommandMap,
+    pub version_specs: VersionSpecs,
+    // the last response of 'act', for its status and headers
+    pub last_response: Option<Response>,
 }
 
 impl Openstack {
@@ -181,6 +232,8 @@ impl Openstack {
             resources: rc,
             actions: ac,
             commands: cm,
+            version_specs: VersionSpecs::new(),
+            last_response: None,
         }
     }
 
@@ -227,20 +280,31 @@ impl Openstack {
         op_args: &HashMap<String, Vec<serde_json::Value>>,
         res_args: &HashMap<String, Vec<serde_json::Value>>,
     ) -> Result<serde_json::Value, OpenstackError> {
+        self.act_paged(op, res, op_args, res_args, None)
+    }
+
+    pub fn act_paged(
+        &mut self,
+        op: String,
+        res: String,
+        op_args: &HashMap<String, Vec<serde_json::Value>>,
+        res_args: &HashMap<String, Vec<serde_json::Value>>,
+        on_page: Option<&mut dyn FnMut(&serde_json::Value)>,
+    ) -> Result<serde_json::Value, OpenstackError> {
 
         if self.connection.endpoints.is_none() {
             self.refresh_token().expect("error while refreshing token");
         }
-        let r = match self.resources.get_resource(res) {
-            Ok(x) => x,
-            Err(e) => return Err(e),
-        };
-
         let is_dry_run = match op_args.get("dry-run") {
             Some(_x) => true,
             None => false,
         };
 
+        let r = match self.resources.get_resource(res) {
+            Ok(x) => self.versioned_resource(x, !is_dry_run),
+            Err(e) => return Err(e),
+        };
+
         // let endpoint: String = match r.resource_type.clone(){
         //     ResourceTypeEnum::ResourceType(x) => x.endpoint,
         //     ResourceTypeEnum::String(x) => x,
@@ -296,7 +360,7 @@ impl Openstack {
                 .clone();
             post_body = Openstack::handle_post_parameters(&r, &matched_op, &new_res_args);
         } else {
-            return Err(OpenstackError::new(&format!("'{}' is not a valid operation", &op)))
+            return Err(OpenstackError::config(&format!("'{}' is not a valid operation", &op)))
         }
 
         // let op_parsed = match OSOperation::from_str(&op){
@@ -309,6 +373,9 @@ impl Openstack {
         for (k, v) in &additional_headers{
             self.connection.client.set_header(k, v);
         }
+        if let Some((k, v)) = self.microversion_header(&r, &new_res_args, &maybe_action, !is_dry_run)? {
+            self.connection.client.set_header(&k, &v);
+        }
 
         // let prepared_url = self.make_url(
         //     matched_op,
@@ -338,6 +405,15 @@ impl Openstack {
             Some(&new_res_args),
         );
 
+        let is_paged = maybe_action.is_none() && (OSOperation::from(op.as_str()) == OSOperation::List);
+        let page_options = match PageOptions::from_args(op_args) {
+            Ok(x) => x,
+            Err(e) => return Err(OpenstackError::config(&e)),
+        };
+        if is_paged {
+            self.set_page_query(&page_options, 0, page_options.marker.clone());
+        }
+
         if is_dry_run {
             // let t = prepared_url.build().expect("Request cannot be build");
             println!("{:?} {:?}\nHeaders: {:?}", self.connection.client.method, self.connection.client.url, self.connection.client.headers);
@@ -348,6 +424,9 @@ impl Openstack {
             return Ok(post_body);
             // return Ok(post_body)
         }
+        if is_paged {
+            return self.list_pages(&page_options, on_page);
+        }
 
         // let mut response = match &post_body {
         //     serde_json::Value::Null => match prepared_url.send() {
@@ -367,7 +446,7 @@ impl Openstack {
                 matched_action = true;
                 let url = match self.connection.client.url.clone(){
                     Some(x) => format!("{}", x),
-                    None => return Err(OpenstackError::new("url argument is required"))
+                    None => return Err(OpenstackError::config("url argument is required"))
                 };
                 let file = get_value(res_args, "file")?;
 
@@ -378,11 +457,11 @@ impl Openstack {
                         let skip_parts_string = get_value(res_args, "skip-parts")?;
                         let skip_first: usize = match skip_parts_string.parse(){
                             Ok(z) => z,
-                            Err(e) => return Err(OpenstackError::new(&format!("{}", e)))
+                            Err(e) => return Err(OpenstackError::config(&format!("{}", e)))
                         };
                         let parts: usize = match parts_string.parse(){
                             Ok(z) => z,
-                            Err(e) => return Err(OpenstackError::new(&format!("{}", e)))
+                            Err(e) => return Err(OpenstackError::config(&format!("{}", e)))
                         };
                         response = self.connection.client.upload_to_object_store_large_skip_parts(&file, &url, &container, &name, parts, skip_first)?;
                     },
@@ -395,7 +474,7 @@ impl Openstack {
                 matched_action = true;
                 let url = match self.connection.client.url.clone(){
                     Some(x) => format!("{}", x),
-                    None => return Err(OpenstackError::new("url argument is required"))
+                    None => return Err(OpenstackError::config("url argument is required"))
                 };
                 let file = get_value(res_args, "file")?;
                 response = self.connection.client.download_from_object_store(&file, &url)?;
@@ -403,13 +482,245 @@ impl Openstack {
         }
         if !matched_action{
             self.connection.client.set_json(post_body);
-            response = self.connection.client.perform()?;
+            response = self.connection.perform()?;
         }
+        self.last_response = Some(response.clone());
         Openstack::handle_response(&mut response)
     }
 
 
 
+    fn set_page_query(&mut self, options: &PageOptions, collected: usize, marker: Option<String>) {
+        let mut url = match self.connection.client.url.clone() {
+            Some(x) => x,
+            None => return,
+        };
+        if let Some(x) = options.request_limit(collected) {
+            url = set_query(&url, "limit", &x.to_string());
+        }
+        if let Some(x) = marker {
+            url = set_query(&url, "marker", &x);
+        }
+        self.connection.client.set_url(&url);
+    }
+
+    fn list_pages(
+        &mut self,
+        options: &PageOptions,
+        mut on_page: Option<&mut dyn FnMut(&serde_json::Value)>,
+    ) -> Result<serde_json::Value, OpenstackError> {
+        // pages are merged, or handed to 'on_page' one by one when a page size is given
+        let stream = options.page_size.is_some() && on_page.is_some();
+        let mut first = serde_json::Value::Null;
+        let mut key: String;
+        let mut items = vec![];
+        let mut collected = 0;
+        let mut fetched = HashSet::new();
+        loop {
+            let url = self.connection.client.url.clone().unwrap_or_default();
+            // a next link or marker that points back to a fetched page would list forever
+            if !fetched.insert(url.clone()) {
+                return Err(OpenstackError::new(&format!("page {} was already fetched, the listing does not advance", url)));
+            }
+            let mut response = self.connection.perform()?;
+            self.last_response = Some(response.clone());
+            let data = Openstack::handle_response(&mut response)?;
+            let mut page = match split_page(&data) {
+                Some((x, y)) => {
+                    key = x;
+                    y
+                }
+                None => return Ok(data),
+            };
+            let next = if page.is_empty() {
+                None
+            } else if key == "" {
+                // swift has no links, the next page starts after the last name
+                let is_last = options.page_size.map_or(false, |x| page.len() < x)
+                    | listing_count(&response).map_or(false, |x| collected + page.len() >= x);
+                if is_last {
+                    None
+                } else {
+                    next_marker(&page).map(|x| set_query(&url, "marker", &x))
+                }
+            } else {
+                next_link(&data, &key).and_then(|x| resolve_link(&url, &x))
+            };
+            if let Some(x) = options.limit {
+                page.truncate(x.saturating_sub(collected));
+            }
+            collected += page.len();
+            if stream {
+                if let Some(ref mut x) = on_page {
+                    x(&merge_pages(&data, &key, page));
+                }
+            } else {
+                if first.is_null() {
+                    first = data;
+                }
+                items.extend(page);
+            }
+            match next {
+                Some(ref x) if options.limit.map_or(true, |y| collected < y) => {
+                    debug!("fetching next page {}", x);
+                    self.connection.client.set_url(x);
+                    self.set_page_query(options, collected, None);
+                }
+                _ => break,
+            }
+        }
+        if stream {
+            return Ok(serde_json::Value::Null);
+        }
+        Ok(merge_pages(&first, &key, items))
+    }
+
+    fn versioned_resource(&mut self, mut res: Resource, discover: bool) -> Resource {
+        // adds the api version to catalog urls without one
+        let (name, endpoint) = match &res.resource_type {
+            ResourceTypeEnum::ResourceType(x) => (x.name.clone(), x.endpoint.clone()),
+            ResourceTypeEnum::String(_x) => return res,
+        };
+        let spec = match self.version_specs.versions.get(&name) {
+            Some(x) => x.clone(),
+            None => return res,
+        };
+        if endpoint_has_version(&endpoint) {
+            return res;
+        }
+        let segment = match self.discover_version(&name, &endpoint, &spec, discover) {
+            Some(x) => x.path_segment(),
+            None => spec.default.clone(),
+        };
+        res.resource_type = ResourceTypeEnum::ResourceType(ResourceType {
+            name,
+            endpoint: versioned_endpoint(&endpoint, &segment),
+        });
+        res
+    }
+
+    pub fn discover_version(
+        &mut self,
+        service_type: &str,
+        endpoint: &str,
+        spec: &VersionSpec,
+        discover: bool,
+    ) -> Option<ServiceVersion> {
+        // failing discovery is not fatal, the default version is used instead
+        if let Some(x) = self.connection.config.versions.get(service_type) {
+            return Some(x.clone());
+        }
+        if !discover {
+            return None;
+        }
+        let response = match self.connection.client.get(&add_slash(endpoint)) {
+            Ok(x) => x,
+            Err(e) => {
+                debug!("version discovery for {} failed: {}", service_type, e);
+                return None;
+            }
+        };
+        let version = pick_version(&parse_versions(&response.response()), &spec.supported)?;
+        debug!("discovered {} version {}", service_type, version.id);
+        self.remember_version(service_type, &version);
+        Some(version)
+    }
+
+    pub fn discover_microversions(
+        &mut self,
+        service_type: &str,
+        endpoint: &str,
+        discover: bool,
+    ) -> Option<ServiceVersion> {
+        // the version document of a versioned endpoint holds the microversion range
+        if let Some(x) = self.connection.config.versions.get(service_type) {
+            return Some(x.clone());
+        }
+        if !discover {
+            return None;
+        }
+        let root = version_root(endpoint);
+        let response = match self.connection.client.get(&root) {
+            Ok(x) => x,
+            Err(e) => {
+                debug!("microversion discovery for {} failed: {}", service_type, e);
+                return None;
+            }
+        };
+        let versions = parse_versions(&response.response());
+        let version = match versions.iter().find(|x| root.ends_with(&format!("/{}/", x.path_segment()))) {
+            Some(x) => x.clone(),
+            None if versions.len() == 1 => versions[0].clone(),
+            None => return None,
+        };
+        debug!(
+            "discovered {} microversions {} to {}",
+            service_type, version.min_version, version.version
+        );
+        self.remember_version(service_type, &version);
+        Some(version)
+    }
+
+    fn remember_version(&mut self, service_type: &str, version: &ServiceVersion) {
+        self.connection
+            .config
+            .versions
+            .insert(service_type.to_string(), version.clone());
+        if self.connection.use_cache {
+            if let Err(e) = self.connection.to_cache() {
+                debug!("unable to cache discovered versions: {}", e);
+            }
+        }
+    }
+
+    fn microversion_header(
+        &mut self,
+        res: &Resource,
+        res_args: &HashMap<String, Vec<serde_json::Value>>,
+        action: &Option<Action>,
+        discover: bool,
+    ) -> Result<Option<(String, String)>, OpenstackError> {
+        let (name, endpoint) = match &res.resource_type {
+            ResourceTypeEnum::ResourceType(x) => (x.name.clone(), x.endpoint.clone()),
+            ResourceTypeEnum::String(_x) => return Ok(None),
+        };
+        let spec = match self.version_specs.microversions.get(&name) {
+            Some(x) => x.clone(),
+            None => return Ok(None),
+        };
+        let parameters = match action {
+            Some(x) => x.post_parameters.clone(),
+            None => res.post_parameters.clone(),
+        };
+        // only given parameters count, defaults never need a higher microversion
+        let required = parameters
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|x| res_args.contains_key(&x.name))
+            .filter_map(|x| {
+                let version = x.min_microversion.as_ref().and_then(|y| parse_microversion(y))?;
+                Some((x.name, version))
+            })
+            .max_by_key(|x| x.1);
+        // a plain major version such as 'compute_api_version: 2' is not a microversion
+        let config = &self.connection.config.config;
+        let requested = config
+            .service_options(&spec.option)
+            .api_version
+            .or_else(|| config.service_options(&name).api_version)
+            .filter(|x| x.contains('.') | (x.to_lowercase() == "latest"));
+        if requested.is_none() && required.is_none() {
+            return Ok(None);
+        }
+        let server = self.discover_microversions(&name, &endpoint, discover);
+        let version = negotiate_microversion(
+            requested.as_ref().map(|x| x.as_str()),
+            required.as_ref().map(|(x, y)| (x.as_str(), *y)),
+            server.as_ref(),
+        )?;
+        Ok(version.map(|x| (spec.header.clone(), spec.header_value(&x))))
+    }
+
     pub fn make_url(
         &mut self,
         com: Command,
@@ -538,10 +849,7 @@ impl Openstack {
         //     )),
         // }
         if !response.is_success() {
-            let error = json!({
-                "error": response.response()
-            });
-            return Err(OpenstackError::new(&serde_json::to_string_pretty(&error).unwrap()));
+            return Err(HttpError::from_response(response).into());
         }
         Ok(response.response().clone())
     }
@@ -678,13 +986,22 @@ impl Openstack {
     }
 }
 
+fn listing_count(response: &Response) -> Option<usize> {
+    // swift sends the total number of containers or objects as a header
+    let headers = response.headers();
+    headers
+        .get("x-container-object-count")
+        .or_else(|| headers.get("x-account-container-count"))
+        .and_then(|x| x.parse().ok())
+}
+
 fn get_value(hashmap: &HashMap<String, Vec<serde_json::Value>>, key: &str) -> Result<String, OpenstackError>{
     let string = match get_first_value_from_hashmap_with_vec(hashmap, key){
         Some(x) => match x{
             serde_json::Value::String(y) => y,
-            _ => return Err(OpenstackError::new(&format!("{} argument is required", key)))
+            _ => return Err(OpenstackError::config(&format!("{} argument is required", key)))
         },
-        None => return Err(OpenstackError::new(&format!("{} argument is required", key)))
+        None => return Err(OpenstackError::config(&format!("{} argument is required", key)))
     };
     Ok(string)
 }
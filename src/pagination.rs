@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use url::Url;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PageOptions {
+    // the maximum number of items over all pages
+    pub limit: Option<usize>,
+    pub marker: Option<String>,
+    // the number of items requested per page, pages are streamed when set
+    pub page_size: Option<usize>,
+}
+
+impl PageOptions {
+    pub fn from_args(args: &HashMap<String, Vec<serde_json::Value>>) -> Result<Self, String> {
+        let get = |key: &str| args.get(key).and_then(|x| x.first()).and_then(|x| x.as_str());
+        let number = |key: &str| match get(key) {
+            Some(x) => match x.parse() {
+                Ok(y) => Ok(Some(y)),
+                Err(_e) => Err(format!("'{}' is not a valid value for {}", x, key)),
+            },
+            None => Ok(None),
+        };
+        Ok(PageOptions {
+            limit: number("limit")?,
+            marker: get("marker").map(|x| x.to_string()),
+            page_size: number("page-size")?,
+        })
+    }
+
+    pub fn request_limit(&self, collected: usize) -> Option<usize> {
+        // never ask for more than the remaining part of the limit
+        match (self.page_size, self.limit) {
+            (Some(x), Some(y)) => Some(x.min(y.saturating_sub(collected))),
+            (Some(x), None) => Some(x),
+            (None, Some(y)) => Some(y.saturating_sub(collected)),
+            (None, None) => None,
+        }
+    }
+}
+
+pub fn split_page(data: &serde_json::Value) -> Option<(String, Vec<serde_json::Value>)> {
+    // swift lists are plain arrays, the other services wrap them in an object
+    if let Some(x) = data.as_array() {
+        return Some((String::new(), x.clone()));
+    }
+    let object = data.as_object()?;
+    let mut lists = object
+        .iter()
+        .filter(|(k, _v)| !k.ends_with("_links") && (k.as_str() != "links"))
+        .filter_map(|(k, v)| v.as_array().map(|x| (k.clone(), x.clone())));
+    let list = lists.next()?;
+    if lists.next().is_some() {
+        return None;
+    }
+    Some(list)
+}
+
+pub fn merge_pages(first: &serde_json::Value, key: &str, items: Vec<serde_json::Value>) -> serde_json::Value {
+    if key == "" {
+        return items.into();
+    }
+    let mut merged = first.clone();
+    if let Some(x) = merged.as_object_mut() {
+        x.remove(&format!("{}_links", key));
+        x.remove("links");
+        x.remove("next");
+        x.insert(key.to_string(), items.into());
+    }
+    merged
+}
+
+pub fn next_link(data: &serde_json::Value, key: &str) -> Option<String> {
+    // nova and neutron use '<key>_links', keystone 'links' and glance 'next'
+    let links = data[format!("{}_links", key)].as_array();
+    if let Some(x) = links.and_then(|y| y.iter().find(|z| z["rel"] == "next")) {
+        return x["href"].as_str().map(|y| y.to_string());
+    }
+    if let Some(x) = data["links"]["next"].as_str() {
+        return Some(x.to_string());
+    }
+    data["next"].as_str().map(|x| x.to_string())
+}
+
+pub fn next_marker(items: &[serde_json::Value]) -> Option<String> {
+    // pseudo directories in swift only have a 'subdir'
+    let last = items.last()?;
+    last["name"]
+        .as_str()
+        .or_else(|| last["subdir"].as_str())
+        .map(|x| x.to_string())
+}
+
+pub fn resolve_link(endpoint: &str, link: &str) -> Option<String> {
+    if let Ok(x) = Url::parse(link) {
+        return Some(x.to_string());
+    }
+    // glance links start at the service root, which can be below a path prefix
+    let base = Url::parse(endpoint).ok()?;
+    let link = link.trim_start_matches('/');
+    let first = link.split(|x| (x == '/') | (x == '?')).next()?;
+    let segments: Vec<&str> = base.path_segments()?.collect();
+    let path = match segments.iter().position(|x| *x == first) {
+        Some(x) if x > 0 => format!("/{}/{}", segments[..x].join("/"), link),
+        _ => format!("/{}", link),
+    };
+    base.join(&path).ok().map(|x| x.to_string())
+}
+
+pub fn set_query(url: &str, key: &str, value: &str) -> String {
+    let mut parsed = match Url::parse(url) {
+        Ok(x) => x,
+        Err(_e) => return url.to_string(),
+    };
+    let pairs: Vec<(String, String)> = parsed
+        .query_pairs()
+        .filter(|(k, _v)| k != key)
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    parsed
+        .query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .append_pair(key, value);
+    parsed.to_string()
+}
+
+#[test]
+fn test_next_link_for_each_service() {
+    let nova = json!({
+        "servers": [{"id": "1"}],
+        "servers_links": [{"rel": "next", "href": "https://compute.example.com/v2.1/servers?marker=1"}]
+    });
+    assert_eq!(next_link(&nova, "servers").unwrap(), "https://compute.example.com/v2.1/servers?marker=1");
+
+    let keystone = json!({"projects": [], "links": {"next": null, "self": "https://identity.example.com/v3/projects"}});
+    assert_eq!(next_link(&keystone, "projects"), None);
+
+    let glance = json!({"images": [{"id": "1"}], "next": "/v2/images?marker=1", "first": "/v2/images"});
+    assert_eq!(next_link(&glance, "images").unwrap(), "/v2/images?marker=1");
+    assert_eq!(
+        resolve_link("https://example.com/image/v2/", "/v2/images?marker=1").unwrap(),
+        "https://example.com/image/v2/images?marker=1"
+    );
+    assert_eq!(
+        resolve_link("https://image.example.com/v2/", "/v2/images?marker=1").unwrap(),
+        "https://image.example.com/v2/images?marker=1"
+    );
+}
+
+#[test]
+fn test_split_and_merge_pages() {
+    let page = json!({
+        "networks": [{"id": "1"}],
+        "networks_links": [{"rel": "next", "href": "https://network.example.com/v2.0/networks?marker=1"}]
+    });
+    let (key, items) = split_page(&page).unwrap();
+    assert_eq!(key, "networks");
+    assert_eq!(items.len(), 1);
+    let merged = merge_pages(&page, &key, vec![json!({"id": "1"}), json!({"id": "2"})]);
+    assert_eq!(merged, json!({"networks": [{"id": "1"}, {"id": "2"}]}));
+
+    let objects = json!([{"name": "a"}, {"subdir": "b/"}]);
+    let (key, items) = split_page(&objects).unwrap();
+    assert_eq!(key, "");
+    assert_eq!(next_marker(&items).unwrap(), "b/");
+    assert!(split_page(&json!({"server": {"id": "1"}})).is_none());
+}
+
+#[test]
+fn test_set_query_replaces_value() {
+    assert_eq!(
+        set_query("https://example.com/servers?limit=10&marker=a", "marker", "b"),
+        "https://example.com/servers?limit=10&marker=b"
+    );
+    let options = PageOptions {
+        limit: Some(25),
+        page_size: Some(10),
+        ..Default::default()
+    };
+    assert_eq!(options.request_limit(0), Some(10));
+    assert_eq!(options.request_limit(20), Some(5));
+}
@@ -0,0 +1,1023 @@
+// a jmespath implementation for --query, see http://jmespath.org/specification.html
+
+use std::cmp::Ordering;
+
+use serde_json::Value as JSONValue;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    QuotedIdentifier(String),
+    Literal(JSONValue),
+    Number(i64),
+    Dot,
+    Star,
+    Flatten,
+    Filter,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    Comma,
+    Colon,
+    Current,
+    Expref,
+    Pipe,
+    Or,
+    And,
+    Not,
+    Comparator(Comparator),
+    Eof,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparator {
+    Eq,
+    Ne,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Identity,
+    Field(String),
+    Index(i64),
+    Slice(Option<i64>, Option<i64>, Option<i64>),
+    Subexpression(Box<Node>, Box<Node>),
+    Projection(Box<Node>, Box<Node>),
+    ValueProjection(Box<Node>, Box<Node>),
+    FilterProjection(Box<Node>, Box<Node>, Box<Node>),
+    Flatten(Box<Node>),
+    Pipe(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Compare(Comparator, Box<Node>, Box<Node>),
+    MultiList(Vec<Node>),
+    MultiHash(Vec<(String, Node)>),
+    Literal(JSONValue),
+    Function(String, Vec<Node>),
+    Expref(Box<Node>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    expression: String,
+    ast: Node,
+}
+
+impl Query {
+    pub fn new(expression: &str) -> Result<Self, String> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser { tokens, position: 0 };
+        let ast = parser.expression(0)?;
+        if parser.current() != &Token::Eof {
+            return Err(format!("unexpected {:?} in query '{}'", parser.current(), expression));
+        }
+        Ok(Query {
+            expression: expression.to_string(),
+            ast,
+        })
+    }
+
+    pub fn search(&self, data: &JSONValue) -> Result<JSONValue, String> {
+        evaluate(&self.ast, data).map_err(|e| format!("{} in query '{}'", e, self.expression))
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+        let (token, length) = match c {
+            ' ' | '\t' | '\n' | '\r' => {
+                i += 1;
+                continue;
+            }
+            'a'..='z' | 'A'..='Z' | '_' => {
+                let end = (i..chars.len())
+                    .find(|x| !(chars[*x].is_ascii_alphanumeric() | (chars[*x] == '_')))
+                    .unwrap_or(chars.len());
+                (Token::Identifier(chars[i..end].iter().collect()), end - i)
+            }
+            '0'..='9' | '-' => {
+                let end = (i + 1..chars.len())
+                    .find(|x| !chars[*x].is_ascii_digit())
+                    .unwrap_or(chars.len());
+                let text: String = chars[i..end].iter().collect();
+                match text.parse() {
+                    Ok(x) => (Token::Number(x), end - i),
+                    Err(_e) => return Err(format!("'{}' is not a valid number", text)),
+                }
+            }
+            '"' => {
+                let end = closing(&chars, i, '"')?;
+                let text: String = chars[i..=end].iter().collect();
+                match serde_json::from_str(&text) {
+                    Ok(x) => (Token::QuotedIdentifier(x), end + 1 - i),
+                    Err(_e) => return Err(format!("{} is not a valid identifier", text)),
+                }
+            }
+            '\'' => {
+                let end = closing(&chars, i, '\'')?;
+                let text: String = chars[i + 1..end].iter().collect();
+                (Token::Literal(text.replace("\\'", "'").into()), end + 1 - i)
+            }
+            '`' => {
+                let end = closing(&chars, i, '`')?;
+                let text: String = chars[i + 1..end].iter().collect();
+                let text = text.replace("\\`", "`");
+                // like jmespath.py, `ACTIVE` that is not json is read as the string "ACTIVE"
+                let legacy = format!("\"{}\"", text.trim_start());
+                match serde_json::from_str(&text).or_else(|_e| serde_json::from_str(&legacy)) {
+                    Ok(x) => (Token::Literal(x), end + 1 - i),
+                    Err(_e) => return Err(format!("`{}` is not a valid json literal", text)),
+                }
+            }
+            '.' => (Token::Dot, 1),
+            '*' => (Token::Star, 1),
+            '@' => (Token::Current, 1),
+            ']' => (Token::RBracket, 1),
+            '{' => (Token::LBrace, 1),
+            '}' => (Token::RBrace, 1),
+            '(' => (Token::LParen, 1),
+            ')' => (Token::RParen, 1),
+            ',' => (Token::Comma, 1),
+            ':' => (Token::Colon, 1),
+            '[' => match next {
+                Some(']') => (Token::Flatten, 2),
+                Some('?') => (Token::Filter, 2),
+                _ => (Token::LBracket, 1),
+            },
+            '&' => match next {
+                Some('&') => (Token::And, 2),
+                _ => (Token::Expref, 1),
+            },
+            '|' => match next {
+                Some('|') => (Token::Or, 2),
+                _ => (Token::Pipe, 1),
+            },
+            '!' => match next {
+                Some('=') => (Token::Comparator(Comparator::Ne), 2),
+                _ => (Token::Not, 1),
+            },
+            '=' => match next {
+                Some('=') => (Token::Comparator(Comparator::Eq), 2),
+                _ => return Err(String::from("use '==' to compare values")),
+            },
+            '<' => match next {
+                Some('=') => (Token::Comparator(Comparator::Lte), 2),
+                _ => (Token::Comparator(Comparator::Lt), 1),
+            },
+            '>' => match next {
+                Some('=') => (Token::Comparator(Comparator::Gte), 2),
+                _ => (Token::Comparator(Comparator::Gt), 1),
+            },
+            x => return Err(format!("unexpected character '{}'", x)),
+        };
+        tokens.push(token);
+        i += length;
+    }
+    tokens.push(Token::Eof);
+    Ok(tokens)
+}
+
+fn closing(chars: &[char], start: usize, quote: char) -> Result<usize, String> {
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            x if x == quote => return Ok(i),
+            _ => i += 1,
+        }
+    }
+    Err(format!("missing closing {}", quote))
+}
+
+fn binding_power(token: &Token) -> u8 {
+    match token {
+        Token::Pipe => 1,
+        Token::Or => 2,
+        Token::And => 3,
+        Token::Comparator(_x) => 5,
+        Token::Flatten => 9,
+        Token::Star => 20,
+        Token::Filter => 21,
+        Token::Dot => 40,
+        Token::Not => 45,
+        Token::LBrace => 50,
+        Token::LBracket => 55,
+        Token::LParen => 60,
+        _ => 0,
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn current(&self) -> &Token {
+        &self.tokens[self.position]
+    }
+
+    fn peek(&self, offset: usize) -> &Token {
+        self.tokens.get(self.position + offset).unwrap_or(&Token::Eof)
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.current().clone();
+        if token != Token::Eof {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), String> {
+        match self.advance() {
+            ref x if x == &token => Ok(()),
+            x => Err(format!("expected {:?} but found {:?}", token, x)),
+        }
+    }
+
+    fn expression(&mut self, power: u8) -> Result<Node, String> {
+        let token = self.advance();
+        let mut left = self.nud(token)?;
+        while power < binding_power(self.current()) {
+            let token = self.advance();
+            left = self.led(token, left)?;
+        }
+        Ok(left)
+    }
+
+    fn nud(&mut self, token: Token) -> Result<Node, String> {
+        match token {
+            Token::Literal(x) => Ok(Node::Literal(x)),
+            Token::Identifier(x) => Ok(Node::Field(x)),
+            Token::QuotedIdentifier(x) => match self.current() {
+                Token::LParen => Err(String::from("quoted identifiers cannot be functions")),
+                _ => Ok(Node::Field(x)),
+            },
+            Token::Star => {
+                let right = match self.current() {
+                    Token::RBracket => Node::Identity,
+                    _ => self.projection_rhs(binding_power(&Token::Star))?,
+                };
+                Ok(Node::ValueProjection(Box::new(Node::Identity), Box::new(right)))
+            }
+            Token::Filter => self.filter(Node::Identity),
+            Token::LBrace => self.multi_hash(),
+            Token::LParen => {
+                let node = self.expression(0)?;
+                self.expect(Token::RParen)?;
+                Ok(node)
+            }
+            Token::Flatten => {
+                let right = self.projection_rhs(binding_power(&Token::Flatten))?;
+                Ok(Node::Projection(Box::new(Node::Flatten(Box::new(Node::Identity))), Box::new(right)))
+            }
+            Token::Not => Ok(Node::Not(Box::new(self.expression(binding_power(&Token::Not))?))),
+            Token::LBracket => match (self.current().clone(), self.peek(1).clone()) {
+                (Token::Number(_), _) | (Token::Colon, _) => {
+                    let right = self.index_expression()?;
+                    self.project_if_slice(Node::Identity, right)
+                }
+                (Token::Star, Token::RBracket) => {
+                    self.advance();
+                    self.advance();
+                    let right = self.projection_rhs(binding_power(&Token::Star))?;
+                    Ok(Node::Projection(Box::new(Node::Identity), Box::new(right)))
+                }
+                _ => self.multi_list(),
+            },
+            Token::Current => Ok(Node::Identity),
+            Token::Expref => Ok(Node::Expref(Box::new(self.expression(0)?))),
+            x => Err(format!("unexpected {:?}", x)),
+        }
+    }
+
+    fn led(&mut self, token: Token, left: Node) -> Result<Node, String> {
+        match token {
+            Token::Dot => match self.current() {
+                Token::Star => {
+                    self.advance();
+                    let right = self.projection_rhs(binding_power(&Token::Dot))?;
+                    Ok(Node::ValueProjection(Box::new(left), Box::new(right)))
+                }
+                _ => {
+                    let right = self.dot_rhs(binding_power(&Token::Dot))?;
+                    Ok(Node::Subexpression(Box::new(left), Box::new(right)))
+                }
+            },
+            Token::Pipe => Ok(Node::Pipe(Box::new(left), Box::new(self.expression(binding_power(&Token::Pipe))?))),
+            Token::Or => Ok(Node::Or(Box::new(left), Box::new(self.expression(binding_power(&Token::Or))?))),
+            Token::And => Ok(Node::And(Box::new(left), Box::new(self.expression(binding_power(&Token::And))?))),
+            Token::Comparator(x) => {
+                let right = self.expression(binding_power(&token))?;
+                Ok(Node::Compare(x, Box::new(left), Box::new(right)))
+            }
+            Token::LParen => {
+                let name = match left {
+                    Node::Field(x) => x,
+                    x => return Err(format!("{:?} is not a function name", x)),
+                };
+                let mut arguments = vec![];
+                if self.current() == &Token::RParen {
+                    self.advance();
+                    return Ok(Node::Function(name, arguments));
+                }
+                loop {
+                    arguments.push(self.expression(0)?);
+                    match self.advance() {
+                        Token::Comma => (),
+                        Token::RParen => break,
+                        x => return Err(format!("expected ',' or ')' but found {:?}", x)),
+                    }
+                }
+                Ok(Node::Function(name, arguments))
+            }
+            Token::Filter => self.filter(left),
+            Token::Flatten => {
+                let right = self.projection_rhs(binding_power(&Token::Flatten))?;
+                Ok(Node::Projection(Box::new(Node::Flatten(Box::new(left))), Box::new(right)))
+            }
+            Token::LBracket => match self.current() {
+                Token::Number(_) | Token::Colon => {
+                    let right = self.index_expression()?;
+                    self.project_if_slice(left, right)
+                }
+                _ => {
+                    self.expect(Token::Star)?;
+                    self.expect(Token::RBracket)?;
+                    let right = self.projection_rhs(binding_power(&Token::Star))?;
+                    Ok(Node::Projection(Box::new(left), Box::new(right)))
+                }
+            },
+            x => Err(format!("unexpected {:?}", x)),
+        }
+    }
+
+    fn filter(&mut self, left: Node) -> Result<Node, String> {
+        let condition = self.expression(0)?;
+        self.expect(Token::RBracket)?;
+        let right = match self.current() {
+            Token::Flatten => Node::Identity,
+            _ => self.projection_rhs(binding_power(&Token::Filter))?,
+        };
+        Ok(Node::FilterProjection(Box::new(left), Box::new(right), Box::new(condition)))
+    }
+
+    fn projection_rhs(&mut self, power: u8) -> Result<Node, String> {
+        // everything that binds weaker than a projection ends it
+        match self.current() {
+            x if binding_power(x) < 10 => Ok(Node::Identity),
+            Token::LBracket | Token::Filter => self.expression(power),
+            Token::Dot => {
+                self.advance();
+                self.dot_rhs(power)
+            }
+            x => Err(format!("unexpected {:?} after a projection", x)),
+        }
+    }
+
+    fn dot_rhs(&mut self, power: u8) -> Result<Node, String> {
+        match self.current() {
+            Token::Identifier(_) | Token::QuotedIdentifier(_) | Token::Star => self.expression(power),
+            Token::LBracket => {
+                self.advance();
+                self.multi_list()
+            }
+            Token::LBrace => {
+                self.advance();
+                self.multi_hash()
+            }
+            x => Err(format!("unexpected {:?} after a dot", x)),
+        }
+    }
+
+    fn index_expression(&mut self) -> Result<Node, String> {
+        if (self.current() == &Token::Colon) | (self.peek(1) == &Token::Colon) {
+            let mut parts = [None, None, None];
+            let mut part = 0;
+            loop {
+                match self.advance() {
+                    Token::RBracket => break,
+                    Token::Colon if part < 2 => part += 1,
+                    Token::Number(x) => parts[part] = Some(x),
+                    x => return Err(format!("unexpected {:?} in a slice", x)),
+                }
+            }
+            if parts[2] == Some(0) {
+                return Err(String::from("a slice step cannot be 0"));
+            }
+            return Ok(Node::Slice(parts[0], parts[1], parts[2]));
+        }
+        let index = match self.advance() {
+            Token::Number(x) => x,
+            x => return Err(format!("expected an index but found {:?}", x)),
+        };
+        self.expect(Token::RBracket)?;
+        Ok(Node::Index(index))
+    }
+
+    fn project_if_slice(&mut self, left: Node, right: Node) -> Result<Node, String> {
+        let is_slice = matches!(right, Node::Slice(_a, _b, _c));
+        let node = Node::Subexpression(Box::new(left), Box::new(right));
+        if is_slice {
+            let right = self.projection_rhs(binding_power(&Token::Star))?;
+            return Ok(Node::Projection(Box::new(node), Box::new(right)));
+        }
+        Ok(node)
+    }
+
+    fn multi_list(&mut self) -> Result<Node, String> {
+        let mut nodes = vec![];
+        loop {
+            nodes.push(self.expression(0)?);
+            match self.advance() {
+                Token::Comma => (),
+                Token::RBracket => break,
+                x => return Err(format!("expected ',' or ']' but found {:?}", x)),
+            }
+        }
+        Ok(Node::MultiList(nodes))
+    }
+
+    fn multi_hash(&mut self) -> Result<Node, String> {
+        let mut pairs = vec![];
+        loop {
+            let key = match self.advance() {
+                Token::Identifier(x) | Token::QuotedIdentifier(x) => x,
+                x => return Err(format!("expected a key but found {:?}", x)),
+            };
+            self.expect(Token::Colon)?;
+            pairs.push((key, self.expression(0)?));
+            match self.advance() {
+                Token::Comma => (),
+                Token::RBrace => break,
+                x => return Err(format!("expected ',' or '}}' but found {:?}", x)),
+            }
+        }
+        Ok(Node::MultiHash(pairs))
+    }
+}
+
+fn is_truthy(value: &JSONValue) -> bool {
+    match value {
+        JSONValue::Null => false,
+        JSONValue::Bool(x) => *x,
+        JSONValue::String(x) => !x.is_empty(),
+        JSONValue::Array(x) => !x.is_empty(),
+        JSONValue::Object(x) => !x.is_empty(),
+        JSONValue::Number(_x) => true,
+    }
+}
+
+fn project(items: &[JSONValue], node: &Node) -> Result<JSONValue, String> {
+    let mut collected = vec![];
+    for item in items {
+        let value = evaluate(node, item)?;
+        if !value.is_null() {
+            collected.push(value);
+        }
+    }
+    Ok(collected.into())
+}
+
+fn slice(items: &[JSONValue], start: Option<i64>, stop: Option<i64>, step: Option<i64>) -> Vec<JSONValue> {
+    let length = items.len() as i64;
+    let step = step.unwrap_or(1);
+    let bound = |x: i64, low: i64, high: i64| {
+        let x = if x < 0 { x + length } else { x };
+        x.max(low).min(high)
+    };
+    let mut collected = vec![];
+    if step > 0 {
+        let mut i = start.map_or(0, |x| bound(x, 0, length));
+        let stop = stop.map_or(length, |x| bound(x, 0, length));
+        while i < stop {
+            collected.push(items[i as usize].clone());
+            i += step;
+        }
+    } else {
+        let mut i = start.map_or(length - 1, |x| bound(x, -1, length - 1));
+        let stop = stop.map_or(-1, |x| bound(x, -1, length - 1));
+        while i > stop {
+            collected.push(items[i as usize].clone());
+            i += step;
+        }
+    }
+    collected
+}
+
+// numbers are equal by value, so `1` == `1.0` like in every other jmespath implementation
+fn equal(left: &JSONValue, right: &JSONValue) -> bool {
+    match (left, right) {
+        (JSONValue::Number(_x), JSONValue::Number(_y)) => left.as_f64() == right.as_f64(),
+        (JSONValue::Array(x), JSONValue::Array(y)) => (x.len() == y.len()) & x.iter().zip(y).all(|(a, b)| equal(a, b)),
+        (JSONValue::Object(x), JSONValue::Object(y)) => {
+            (x.len() == y.len()) & x.iter().all(|(k, v)| y.get(k).map_or(false, |z| equal(v, z)))
+        }
+        _ => left == right,
+    }
+}
+
+fn compare(comparator: Comparator, left: &JSONValue, right: &JSONValue) -> JSONValue {
+    match comparator {
+        Comparator::Eq => equal(left, right).into(),
+        Comparator::Ne => (!equal(left, right)).into(),
+        _ => match (left.as_f64(), right.as_f64()) {
+            (Some(x), Some(y)) => match comparator {
+                Comparator::Lt => (x < y).into(),
+                Comparator::Lte => (x <= y).into(),
+                Comparator::Gt => (x > y).into(),
+                _ => (x >= y).into(),
+            },
+            _ => JSONValue::Null,
+        },
+    }
+}
+
+fn evaluate(node: &Node, data: &JSONValue) -> Result<JSONValue, String> {
+    match node {
+        Node::Identity => Ok(data.clone()),
+        Node::Field(x) => Ok(data.get(x).cloned().unwrap_or(JSONValue::Null)),
+        Node::Index(x) => match data.as_array() {
+            Some(y) => {
+                let index = if *x < 0 { y.len() as i64 + x } else { *x };
+                Ok(y.get(index as usize).filter(|_z| index >= 0).cloned().unwrap_or(JSONValue::Null))
+            }
+            None => Ok(JSONValue::Null),
+        },
+        Node::Slice(a, b, c) => match data.as_array() {
+            Some(x) => Ok(slice(x, *a, *b, *c).into()),
+            None => Ok(JSONValue::Null),
+        },
+        Node::Subexpression(left, right) => evaluate(right, &evaluate(left, data)?),
+        Node::Pipe(left, right) => evaluate(right, &evaluate(left, data)?),
+        Node::Projection(left, right) => match evaluate(left, data)? {
+            JSONValue::Array(x) => project(&x, right),
+            _ => Ok(JSONValue::Null),
+        },
+        Node::ValueProjection(left, right) => match evaluate(left, data)? {
+            JSONValue::Object(x) => project(&x.values().cloned().collect::<Vec<JSONValue>>(), right),
+            _ => Ok(JSONValue::Null),
+        },
+        Node::FilterProjection(left, right, condition) => match evaluate(left, data)? {
+            JSONValue::Array(x) => {
+                let mut kept = vec![];
+                for item in x {
+                    if is_truthy(&evaluate(condition, &item)?) {
+                        kept.push(item);
+                    }
+                }
+                project(&kept, right)
+            }
+            _ => Ok(JSONValue::Null),
+        },
+        Node::Flatten(inner) => match evaluate(inner, data)? {
+            JSONValue::Array(x) => {
+                let mut flat = vec![];
+                for item in x {
+                    match item {
+                        JSONValue::Array(y) => flat.extend(y),
+                        y => flat.push(y),
+                    }
+                }
+                Ok(flat.into())
+            }
+            _ => Ok(JSONValue::Null),
+        },
+        Node::Or(left, right) => {
+            let value = evaluate(left, data)?;
+            match is_truthy(&value) {
+                true => Ok(value),
+                false => evaluate(right, data),
+            }
+        }
+        Node::And(left, right) => {
+            let value = evaluate(left, data)?;
+            match is_truthy(&value) {
+                true => evaluate(right, data),
+                false => Ok(value),
+            }
+        }
+        Node::Not(inner) => Ok((!is_truthy(&evaluate(inner, data)?)).into()),
+        Node::Compare(x, left, right) => Ok(compare(*x, &evaluate(left, data)?, &evaluate(right, data)?)),
+        Node::MultiList(nodes) => {
+            if data.is_null() {
+                return Ok(JSONValue::Null);
+            }
+            let mut values = vec![];
+            for x in nodes {
+                values.push(evaluate(x, data)?);
+            }
+            Ok(values.into())
+        }
+        Node::MultiHash(pairs) => {
+            if data.is_null() {
+                return Ok(JSONValue::Null);
+            }
+            let mut map = serde_json::Map::new();
+            for (k, v) in pairs {
+                map.insert(k.clone(), evaluate(v, data)?);
+            }
+            Ok(map.into())
+        }
+        Node::Literal(x) => Ok(x.clone()),
+        Node::Expref(_x) => Err(String::from("'&' can only be used as a function argument")),
+        Node::Function(name, arguments) => call_function(name, arguments, data),
+    }
+}
+
+fn sort_values(a: &JSONValue, b: &JSONValue) -> Result<Ordering, String> {
+    match (a, b) {
+        (JSONValue::Number(_x), JSONValue::Number(_y)) => {
+            Ok(a.as_f64().partial_cmp(&b.as_f64()).unwrap_or(Ordering::Equal))
+        }
+        (JSONValue::String(x), JSONValue::String(y)) => Ok(x.cmp(y)),
+        _ => Err(String::from("only numbers or strings can be sorted")),
+    }
+}
+
+fn numbers(value: &JSONValue, name: &str) -> Result<Vec<f64>, String> {
+    let items = match value {
+        JSONValue::Array(x) => x,
+        _ => return Err(format!("{}() needs a list of numbers", name)),
+    };
+    items
+        .iter()
+        .map(|x| x.as_f64().ok_or_else(|| format!("{}() needs a list of numbers", name)))
+        .collect()
+}
+
+// whole numbers stay integers, so floor(`1.5`) prints 1 and not 1.0
+fn whole_number(x: f64) -> JSONValue {
+    match (x.fract() == 0.0) & (x.abs() < i64::MAX as f64) {
+        true => (x as i64).into(),
+        false => x.into(),
+    }
+}
+
+fn call_function(name: &str, arguments: &[Node], data: &JSONValue) -> Result<JSONValue, String> {
+    let expects = |count: usize| match arguments.len() == count {
+        true => Ok(()),
+        false => Err(format!("{}() takes {} argument(s)", name, count)),
+    };
+    // map takes the expression first and keeps the nulls it returns
+    if name == "map" {
+        expects(2)?;
+        let key = match &arguments[0] {
+            Node::Expref(x) => x,
+            _ => return Err(String::from("map() needs an expression like &name")),
+        };
+        let items = match evaluate(&arguments[1], data)? {
+            JSONValue::Array(x) => x,
+            _ => return Err(String::from("map() needs a list")),
+        };
+        let mut mapped = vec![];
+        for item in items {
+            mapped.push(evaluate(key, &item)?);
+        }
+        return Ok(mapped.into());
+    }
+    // sort_by, max_by and min_by take an expression that is evaluated for every item
+    if let Some(Node::Expref(key)) = arguments.get(1) {
+        expects(2)?;
+        let items = match evaluate(&arguments[0], data)? {
+            JSONValue::Array(x) => x,
+            _ => return Err(format!("{}() needs a list", name)),
+        };
+        let mut keyed = vec![];
+        for item in items {
+            keyed.push((evaluate(key, &item)?, item));
+        }
+        let mut error = None;
+        keyed.sort_by(|a, b| {
+            sort_values(&a.0, &b.0).unwrap_or_else(|e| {
+                error = Some(e);
+                Ordering::Equal
+            })
+        });
+        if let Some(e) = error {
+            return Err(e);
+        }
+        return match name {
+            "sort_by" => Ok(keyed.into_iter().map(|x| x.1).collect::<Vec<JSONValue>>().into()),
+            "max_by" => Ok(keyed.pop().map(|x| x.1).unwrap_or(JSONValue::Null)),
+            "min_by" => Ok(keyed.into_iter().next().map(|x| x.1).unwrap_or(JSONValue::Null)),
+            x => Err(format!("{}() does not take an expression", x)),
+        };
+    }
+    if let "sort_by" | "max_by" | "min_by" = name {
+        expects(2)?;
+        return Err(format!("{}() needs an expression like &name", name));
+    }
+    let mut values = vec![];
+    for x in arguments {
+        values.push(evaluate(x, data)?);
+    }
+    match name {
+        "length" => {
+            expects(1)?;
+            match &values[0] {
+                JSONValue::String(x) => Ok(x.chars().count().into()),
+                JSONValue::Array(x) => Ok(x.len().into()),
+                JSONValue::Object(x) => Ok(x.len().into()),
+                _ => Err(String::from("length() needs a string, list or object")),
+            }
+        }
+        "keys" | "values" => {
+            expects(1)?;
+            match &values[0] {
+                JSONValue::Object(x) if name == "keys" => Ok(x.keys().cloned().collect::<Vec<String>>().into()),
+                JSONValue::Object(x) => Ok(x.values().cloned().collect::<Vec<JSONValue>>().into()),
+                _ => Err(format!("{}() needs an object", name)),
+            }
+        }
+        "sort" | "max" | "min" | "reverse" => {
+            expects(1)?;
+            if let (JSONValue::String(x), "reverse") = (&values[0], name) {
+                return Ok(x.chars().rev().collect::<String>().into());
+            }
+            let mut items = match &values[0] {
+                JSONValue::Array(x) => x.clone(),
+                _ => return Err(format!("{}() needs a list", name)),
+            };
+            if name == "reverse" {
+                items.reverse();
+                return Ok(items.into());
+            }
+            let mut error = None;
+            items.sort_by(|a, b| {
+                sort_values(a, b).unwrap_or_else(|e| {
+                    error = Some(e);
+                    Ordering::Equal
+                })
+            });
+            if let Some(e) = error {
+                return Err(e);
+            }
+            match name {
+                "max" => Ok(items.pop().unwrap_or(JSONValue::Null)),
+                "min" => Ok(items.into_iter().next().unwrap_or(JSONValue::Null)),
+                _ => Ok(items.into()),
+            }
+        }
+        "sum" => {
+            expects(1)?;
+            let floats = numbers(&values[0], name)?;
+            let integers: Option<Vec<i64>> = values[0].as_array().unwrap().iter().map(|x| x.as_i64()).collect();
+            match integers.and_then(|x| x.into_iter().try_fold(0i64, |a, b| a.checked_add(b))) {
+                Some(x) => Ok(x.into()),
+                None => Ok(floats.iter().sum::<f64>().into()),
+            }
+        }
+        "avg" => {
+            expects(1)?;
+            let floats = numbers(&values[0], name)?;
+            match floats.is_empty() {
+                true => Ok(JSONValue::Null),
+                false => Ok((floats.iter().sum::<f64>() / floats.len() as f64).into()),
+            }
+        }
+        "abs" | "ceil" | "floor" => {
+            expects(1)?;
+            if let (Some(x), "abs") = (values[0].as_i64(), name) {
+                return Ok(x.checked_abs().map_or_else(|| (x as f64).abs().into(), |y| y.into()));
+            }
+            match (values[0].as_f64(), name) {
+                (Some(x), "abs") => Ok(x.abs().into()),
+                (Some(x), "ceil") => Ok(whole_number(x.ceil())),
+                (Some(x), _) => Ok(whole_number(x.floor())),
+                (None, _) => Err(format!("{}() needs a number", name)),
+            }
+        }
+        "contains" => {
+            expects(2)?;
+            match (&values[0], &values[1]) {
+                (JSONValue::Array(x), y) => Ok(x.iter().any(|z| equal(z, y)).into()),
+                (JSONValue::String(x), JSONValue::String(y)) => Ok(x.contains(y.as_str()).into()),
+                _ => Err(String::from("contains() needs a list or a string")),
+            }
+        }
+        "starts_with" | "ends_with" => {
+            expects(2)?;
+            match (&values[0], &values[1]) {
+                (JSONValue::String(x), JSONValue::String(y)) if name == "starts_with" => Ok(x.starts_with(y.as_str()).into()),
+                (JSONValue::String(x), JSONValue::String(y)) => Ok(x.ends_with(y.as_str()).into()),
+                _ => Err(format!("{}() needs two strings", name)),
+            }
+        }
+        "join" => {
+            expects(2)?;
+            match (&values[0], &values[1]) {
+                (JSONValue::String(x), JSONValue::Array(y)) => {
+                    let parts: Option<Vec<&str>> = y.iter().map(|z| z.as_str()).collect();
+                    match parts {
+                        Some(z) => Ok(z.join(x).into()),
+                        None => Err(String::from("join() needs a list of strings")),
+                    }
+                }
+                _ => Err(String::from("join() needs a separator and a list of strings")),
+            }
+        }
+        "to_string" => {
+            expects(1)?;
+            match &values[0] {
+                JSONValue::String(x) => Ok(x.clone().into()),
+                x => Ok(x.to_string().into()),
+            }
+        }
+        "to_number" => {
+            expects(1)?;
+            match &values[0] {
+                JSONValue::Number(x) => Ok(JSONValue::Number(x.clone())),
+                JSONValue::String(x) => match x.parse::<i64>() {
+                    Ok(y) => Ok(y.into()),
+                    Err(_e) => Ok(x.parse::<f64>().ok().map(|y| y.into()).unwrap_or(JSONValue::Null)),
+                },
+                _ => Ok(JSONValue::Null),
+            }
+        }
+        "type" => {
+            expects(1)?;
+            let kind = match &values[0] {
+                JSONValue::Null => "null",
+                JSONValue::Bool(_x) => "boolean",
+                JSONValue::Number(_x) => "number",
+                JSONValue::String(_x) => "string",
+                JSONValue::Array(_x) => "array",
+                JSONValue::Object(_x) => "object",
+            };
+            Ok(kind.into())
+        }
+        "to_array" => {
+            expects(1)?;
+            match &values[0] {
+                JSONValue::Array(x) => Ok(JSONValue::Array(x.clone())),
+                x => Ok(vec![x.clone()].into()),
+            }
+        }
+        "merge" | "not_null" if values.is_empty() => Err(format!("{}() takes at least 1 argument", name)),
+        "merge" => {
+            let mut merged = serde_json::Map::new();
+            for x in values {
+                match x {
+                    JSONValue::Object(y) => merged.extend(y),
+                    _ => return Err(String::from("merge() needs objects")),
+                }
+            }
+            Ok(merged.into())
+        }
+        "not_null" => Ok(values.into_iter().find(|x| !x.is_null()).unwrap_or(JSONValue::Null)),
+        x => Err(format!("unknown function {}()", x)),
+    }
+}
+
+#[test]
+fn test_query_fields_indexes_and_projections() {
+    let data = json!({"servers": [
+        {"id": "1", "name": "web", "status": "ACTIVE", "addresses": {"private": [{"addr": "10.0.0.1"}]}},
+        {"id": "2", "name": "db", "status": "ERROR", "addresses": {"private": [{"addr": "10.0.0.2"}]}},
+        {"id": "3", "name": "cache", "status": "ACTIVE", "addresses": {}}
+    ]});
+    let search = |x: &str| Query::new(x).unwrap().search(&data).unwrap();
+
+    assert_eq!(search("servers[0].id"), json!("1"));
+    assert_eq!(search("servers[-1].name"), json!("cache"));
+    assert_eq!(search("servers[].id"), json!(["1", "2", "3"]));
+    assert_eq!(search("servers[*].addresses.private[0].addr"), json!(["10.0.0.1", "10.0.0.2"]));
+    assert_eq!(search("servers[].addresses.private"), json!([[{"addr": "10.0.0.1"}], [{"addr": "10.0.0.2"}]]));
+    assert_eq!(search("servers[].addresses.private[].addr"), json!(["10.0.0.1", "10.0.0.2"]));
+    assert_eq!(search("servers[:2].name"), json!(["web", "db"]));
+    assert_eq!(search("servers[::-1].id"), json!(["3", "2", "1"]));
+    assert_eq!(search("servers[0].addresses.*[0]"), json!([{"addr": "10.0.0.1"}]));
+    assert_eq!(search("missing.field"), JSONValue::Null);
+}
+
+#[test]
+fn test_query_filters_and_multiselect() {
+    let data = json!({"volumes": [
+        {"id": "a", "size": 10, "status": "available", "bootable": "true"},
+        {"id": "b", "size": 40, "status": "in-use", "bootable": "false"},
+        {"id": "c", "size": 20, "status": "available", "bootable": "false"}
+    ]});
+    let search = |x: &str| Query::new(x).unwrap().search(&data).unwrap();
+
+    assert_eq!(search("volumes[?status=='available'].id"), json!(["a", "c"]));
+    assert_eq!(search("volumes[?size > `15` && status != 'in-use'].id"), json!(["c"]));
+    assert_eq!(search("volumes[?!(size < `20`)].id | [0]"), json!("b"));
+    assert_eq!(
+        search("volumes[?bootable=='true'].{ID: id, Size: size}"),
+        json!([{"ID": "a", "Size": 10}])
+    );
+    assert_eq!(search("volumes[0].[id, size]"), json!(["a", 10]));
+    assert_eq!(search("length(volumes)"), json!(3));
+    assert_eq!(search("sort_by(volumes, &size)[-1].id"), json!("b"));
+    assert_eq!(search("max_by(volumes, &size).id"), json!("b"));
+    assert_eq!(search("join(', ', volumes[].id)"), json!("a, b, c"));
+    assert_eq!(search("sum(volumes[].size)"), json!(70));
+    assert_eq!(search("volumes[?contains(id, 'b')].\"status\""), json!(["in-use"]));
+}
+
+#[test]
+fn test_query_errors() {
+    assert!(Query::new("servers[").is_err());
+    assert!(Query::new("servers[?status='ACTIVE']").is_err());
+    assert!(Query::new("servers.").is_err());
+    assert!(Query::new("foo(").is_err());
+    let query = Query::new("unknown(servers)").unwrap();
+    assert_eq!(
+        query.search(&json!({})).unwrap_err(),
+        "unknown function unknown() in query 'unknown(servers)'"
+    );
+}
+
+#[test]
+fn test_query_spec_compliance() {
+    // examples from the jmespath specification and its compliance suite
+    let data = json!({
+        "people": [
+            {"name": "b", "age": 30, "tags": ["x"]},
+            {"name": "a", "age": 50, "tags": ["y", "z"]},
+            {"name": "c", "age": null, "tags": []}
+        ],
+        "numbers": [-1, 2.5, 3, -4.5],
+        "integers": [1, 2, 3, 4],
+        "empty": [],
+        "a": {"x": 1, "y": 2},
+        "b": {"y": 3, "z": 4},
+        "one": 1,
+        "one_float": 1.0
+    });
+    let cases = vec![
+        ("`1` == `1.0`", json!(true)),
+        ("one == one_float", json!(true)),
+        ("`[1, {\"a\": 2}]` == `[1.0, {\"a\": 2.0}]`", json!(true)),
+        ("`1` != `1.0`", json!(false)),
+        ("contains(`[1, 2]`, `2.0`)", json!(true)),
+        ("sum(integers)", json!(10)),
+        ("sum(numbers)", json!(0.0)),
+        ("sum(empty)", json!(0)),
+        ("avg(integers)", json!(2.5)),
+        ("avg(empty)", JSONValue::Null),
+        ("abs(`-1`)", json!(1)),
+        ("abs(`-1.5`)", json!(1.5)),
+        ("ceil(`1.001`)", json!(2)),
+        ("floor(`1.9`)", json!(1)),
+        ("numbers[].abs(@)", json!([1, 2.5, 3, 4.5])),
+        ("map(&name, people)", json!(["b", "a", "c"])),
+        ("map(&age, people)", json!([30, 50, null])),
+        ("map(&[], `[[1, 2, 3, [4]], [5, 6, 7, [8, 9]]]`)", json!([[1, 2, 3, 4], [5, 6, 7, 8, 9]])),
+        ("merge(a, b)", json!({"x": 1, "y": 3, "z": 4})),
+        ("merge(a)", json!({"x": 1, "y": 2})),
+        ("to_array(one)", json!([1])),
+        ("to_array(integers)", json!([1, 2, 3, 4])),
+        ("to_array(people[0].tags)", json!(["x"])),
+        ("not_null(missing, people[2].age, one)", json!(1)),
+        ("not_null(missing)", JSONValue::Null),
+        ("length(people[?age > `40`])", json!(1)),
+        ("people[?age == `30.0`].name", json!(["b"])),
+        ("people[?name == `a`].age", json!([50])),
+        ("`foo bar`", json!("foo bar")),
+        ("to_number('1')", json!(1)),
+        ("to_number('-12')", json!(-12)),
+        ("to_number('1.5')", json!(1.5)),
+        ("to_number('x')", JSONValue::Null),
+    ];
+    for (expression, expected) in cases {
+        let result = Query::new(expression).unwrap().search(&data).unwrap();
+        assert_eq!(result, expected, "{}", expression);
+    }
+
+    for expression in vec!["sum(a b)", "sum(a,)", "merge(a,,b)", "not_null(,a)", "length(a b c)"] {
+        assert!(Query::new(expression).is_err(), "{}", expression);
+    }
+    let errors = vec![
+        ("not_null()", "not_null() takes at least 1 argument"),
+        ("merge()", "merge() takes at least 1 argument"),
+        ("merge(a, one)", "merge() needs objects"),
+        ("sum(people)", "sum() needs a list of numbers"),
+        ("avg(one)", "avg() needs a list of numbers"),
+        ("abs('x')", "abs() needs a number"),
+        ("map(name, people)", "map() needs an expression like &name"),
+        ("map(&name)", "map() takes 2 argument(s)"),
+        ("sort_by(people, name)", "sort_by() needs an expression like &name"),
+        ("max_by(people, age)", "max_by() needs an expression like &name"),
+        ("min_by(people)", "min_by() takes 2 argument(s)"),
+    ];
+    for (expression, message) in errors {
+        let error = Query::new(expression).unwrap().search(&data).unwrap_err();
+        assert_eq!(error, format!("{} in query '{}'", message, expression));
+    }
+}
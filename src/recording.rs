@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+
+use serde_json::Value as JSONValue;
+
+use client::Response;
+use error::OpenstackError;
+use headers::HeaderMap;
+use trace::{redact_header, redact_json};
+
+// one line in a recording, secrets are redacted before they are written
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Exchange {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub body: JSONValue,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RecordedResponse {
+    pub status: u32,
+    #[serde(default)]
+    pub headers: JSONValue,
+    #[serde(default)]
+    pub body: JSONValue,
+}
+
+impl RecordedRequest {
+    pub fn new(method: &str, url: &str, body: Option<&JSONValue>) -> Self {
+        RecordedRequest {
+            method: method.to_uppercase(),
+            url: url.to_string(),
+            body: body.map(redact_json).unwrap_or(JSONValue::Null),
+        }
+    }
+
+    fn matches(&self, other: &RecordedRequest) -> bool {
+        self.method.eq_ignore_ascii_case(&other.method) && (self.url == other.url) && (self.body == other.body)
+    }
+}
+
+impl Exchange {
+    pub fn new(request: RecordedRequest, response: &Response) -> Self {
+        let mut headers = HeaderMap::new();
+        for (k, v) in response.headers().iter() {
+            headers.append(k, &redact_header(k, v));
+        }
+        Exchange {
+            request,
+            response: RecordedResponse {
+                status: response.status(),
+                headers: headers.to_json(),
+                body: redact_json(&response.response()),
+            },
+        }
+    }
+
+    pub fn to_response(&self) -> Response {
+        Response(
+            self.response.body.clone(),
+            self.response.status,
+            HeaderMap::from_json(&self.response.headers),
+        )
+    }
+}
+
+pub fn start_recording(path: &str) -> Result<(), OpenstackError> {
+    // every run starts with an empty file, the clients append to it
+    File::create(path)?;
+    Ok(())
+}
+
+pub fn record(path: &str, exchange: &Exchange) -> Result<(), OpenstackError> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(exchange)?)?;
+    Ok(())
+}
+
+pub fn read_recording(path: &str) -> Result<Vec<Exchange>, OpenstackError> {
+    let file = File::open(path)?;
+    let mut exchanges = vec![];
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        exchanges.push(serde_json::from_str(&line)?);
+    }
+    Ok(exchanges)
+}
+
+#[derive(Debug, Default)]
+pub struct Replay {
+    exchanges: Vec<Exchange>,
+    // how often each exchange was served, repeated requests get the next matching one
+    served: HashMap<usize, usize>,
+}
+
+impl Replay {
+    pub fn from_file(path: &str) -> Result<Self, OpenstackError> {
+        Ok(Replay {
+            exchanges: read_recording(path)?,
+            served: HashMap::new(),
+        })
+    }
+
+    pub fn respond(&mut self, request: &RecordedRequest) -> Result<Response, OpenstackError> {
+        let matching: Vec<usize> = (0..self.exchanges.len())
+            .filter(|x| self.exchanges[*x].request.matches(request))
+            .collect();
+        // the last match keeps answering once every match has been used
+        let index = match matching.iter().find(|x| !self.served.contains_key(x)).or_else(|| matching.last()) {
+            Some(x) => *x,
+            None => {
+                return Err(OpenstackError::config(&format!(
+                    "no recorded response for {} {}",
+                    request.method, request.url
+                )))
+            }
+        };
+        *self.served.entry(index).or_insert(0) += 1;
+        Ok(self.exchanges[index].to_response())
+    }
+}
+
+#[test]
+fn test_replay_matches_method_url_and_body() {
+    let line = |method: &str, url: &str, body: JSONValue, status: u32| Exchange {
+        request: RecordedRequest {
+            method: method.to_string(),
+            url: url.to_string(),
+            body,
+        },
+        response: RecordedResponse {
+            status,
+            headers: json!({"x-openstack-request-id": "req-1"}),
+            body: json!({"status": status}),
+        },
+    };
+    let mut replay = Replay {
+        exchanges: vec![
+            line("GET", "https://example.com/servers/1", JSONValue::Null, 503),
+            line("GET", "https://example.com/servers/1", JSONValue::Null, 200),
+            line("POST", "https://example.com/servers", json!({"server": {"name": "a"}}), 202),
+            line("POST", "https://example.com/servers", json!({"server": {"name": "b"}}), 400),
+        ],
+        served: HashMap::new(),
+    };
+
+    let get = RecordedRequest::new("get", "https://example.com/servers/1", None);
+    assert_eq!(replay.respond(&get).unwrap().status(), 503);
+    assert_eq!(replay.respond(&get).unwrap().status(), 200);
+    assert_eq!(replay.respond(&get).unwrap().status(), 200);
+
+    let post = RecordedRequest::new("POST", "https://example.com/servers", Some(&json!({"server": {"name": "b"}})));
+    let response = replay.respond(&post).unwrap();
+    assert_eq!(response.status(), 400);
+    assert_eq!(response.request_id(), Some("req-1".to_string()));
+
+    let missing = RecordedRequest::new("DELETE", "https://example.com/servers/1", None);
+    let error = replay.respond(&missing).unwrap_err();
+    assert_eq!(error.kind(), "config");
+    assert_eq!(error.exit_code(), 2);
+}
+
+#[test]
+fn test_exchange_redacts_secrets() {
+    let request = RecordedRequest::new(
+        "POST",
+        "https://example.com/v3/auth/tokens",
+        Some(&json!({"auth": {"identity": {"password": {"user": {"name": "admin", "password": "hunter2"}}}}})),
+    );
+    let response = Response(
+        json!({"token": {"expires_at": "2099-01-01T00:00:00Z"}}),
+        201,
+        HeaderMap::from_lines(&["X-Subject-Token: gAAAA\r\n", "Vary: X-Auth-Token\r\n"]),
+    );
+    let exchange = Exchange::new(request, &response);
+    assert_eq!(exchange.request.body["auth"]["identity"]["password"]["user"]["password"], "<redacted>");
+    assert_eq!(exchange.response.headers, json!({"x-subject-token": "<redacted>", "vary": "X-Auth-Token"}));
+    assert_eq!(exchange.to_response().headers().get("Vary"), Some("X-Auth-Token"));
+}
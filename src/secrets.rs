@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Read};
+use std::path::PathBuf;
+
+use cache::{create_private_dir, create_private_file};
+use traits::SecretStore;
+
+#[cfg(feature = "keyring")]
+const SERVICE_NAME: &str = "openstack-client";
+
+pub fn default_store() -> Result<Box<dyn SecretStore>, Error> {
+    // a file store can be used where no keyring is available, such as in tests
+    if let Some(x) = std::env::var_os("OS_CLIENT_SECRET_FILE") {
+        return Ok(Box::new(FileStore::new(PathBuf::from(x))));
+    }
+    keyring_store()
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_store() -> Result<Box<dyn SecretStore>, Error> {
+    Ok(Box::new(KeyringStore))
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_store() -> Result<Box<dyn SecretStore>, Error> {
+    Err(Error::new(
+        ErrorKind::NotFound,
+        "no secret store available, build with '--features keyring' or set OS_CLIENT_SECRET_FILE",
+    ))
+}
+
+#[cfg(feature = "keyring")]
+pub struct KeyringStore;
+
+#[cfg(feature = "keyring")]
+impl KeyringStore {
+    fn entry(key: &str) -> Result<keyring::Entry, Error> {
+        keyring::Entry::new(SERVICE_NAME, key).map_err(Self::to_error)
+    }
+
+    fn to_error(err: keyring::Error) -> Error {
+        Error::new(ErrorKind::Other, err.to_string())
+    }
+}
+
+#[cfg(feature = "keyring")]
+impl SecretStore for KeyringStore {
+    fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        match Self::entry(key)?.get_password() {
+            Ok(x) => Ok(Some(x)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(Self::to_error(e)),
+        }
+    }
+
+    fn set(&self, key: &str, secret: &str) -> Result<(), Error> {
+        Self::entry(key)?.set_password(secret).map_err(Self::to_error)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Error> {
+        match Self::entry(key)?.delete_password() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(Self::to_error(e)),
+        }
+    }
+}
+
+pub struct FileStore {
+    pub path: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(path: PathBuf) -> Self {
+        FileStore { path }
+    }
+
+    fn read(&self) -> Result<HashMap<String, String>, Error> {
+        let mut data = String::new();
+        match fs::File::open(&self.path) {
+            Ok(mut x) => x.read_to_string(&mut data)?,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(e) => return Err(e),
+        };
+        let secrets = serde_json::from_str(&data)?;
+        Ok(secrets)
+    }
+
+    fn write(&self, secrets: &HashMap<String, String>) -> Result<(), Error> {
+        if let Some(x) = self.path.parent() {
+            create_private_dir(x)?;
+        }
+        let file = create_private_file(&self.path)?;
+        serde_json::to_writer(file, secrets)?;
+        Ok(())
+    }
+}
+
+impl SecretStore for FileStore {
+    fn get(&self, key: &str) -> Result<Option<String>, Error> {
+        Ok(self.read()?.remove(key))
+    }
+
+    fn set(&self, key: &str, secret: &str) -> Result<(), Error> {
+        let mut secrets = self.read()?;
+        secrets.insert(key.to_string(), secret.to_string());
+        self.write(&secrets)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Error> {
+        let mut secrets = self.read()?;
+        if secrets.remove(key).is_some() {
+            self.write(&secrets)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn test_file_store(name: &str) -> FileStore {
+    let path = std::env::temp_dir()
+        .join(format!("openstack-client-secrets-{}", std::process::id()))
+        .join(name);
+    let _ = fs::remove_file(&path);
+    FileStore::new(path)
+}
+
+#[test]
+fn test_file_store_set_get_delete() {
+    let store = test_file_store("set_get_delete.json");
+
+    assert_eq!(store.get("cloud/user").unwrap(), None);
+    store.set("cloud/user", "password").unwrap();
+    store.set("other/user", "other").unwrap();
+    assert_eq!(store.get("cloud/user").unwrap(), Some("password".to_string()));
+
+    store.delete("cloud/user").unwrap();
+    assert_eq!(store.get("cloud/user").unwrap(), None);
+    assert_eq!(store.get("other/user").unwrap(), Some("other".to_string()));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_file_store_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+    let store = test_file_store("private.json");
+    store.set("cloud/user", "password").unwrap();
+
+    let mode = fs::metadata(&store.path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::io::{Error, ErrorKind};
 use utils::{convert_to_multiple, compare_different_cases, get_first_value_from_hashmap_with_vec};
 
+use discovery::VersionSpecs;
 use error::OpenstackError;
 
 
@@ -49,6 +50,8 @@ pub struct PostParameter{
     pub placement: String,
     #[serde(default = "just_return_string", rename = "type")]
     pub the_type: String,
+    // the lowest microversion of the service that accepts this parameter
+    pub min_microversion: Option<String>,
 }
 
 
@@ -245,6 +248,15 @@ impl ResourceMap{
         for (k, v) in m.iter(){
             resource_types.insert(k.clone(), ResourceTypeEnum::ResourceType(ResourceType{name: k.clone(), endpoint: v.clone()}));
         }
+        // newer clouds list for example 'block-storage' instead of 'volumev3'
+        for (k, aliases) in VersionSpecs::new().aliases.iter(){
+            if resource_types.contains_key(k){
+                continue
+            }
+            if let Some(v) = aliases.iter().filter_map(|x| m.get(x)).next(){
+                resource_types.insert(k.clone(), ResourceTypeEnum::ResourceType(ResourceType{name: k.clone(), endpoint: v.clone()}));
+            }
+        }
 
         for res in self.map.values_mut(){
             if let ResourceTypeEnum::String(x) = res.resource_type.clone(){
@@ -267,7 +279,7 @@ impl ResourceMap{
             }
         }
         if !found{
-            return Err(OpenstackError::new(&format!("'{}' is not a valid resource", &user_input)))
+            return Err(OpenstackError::config(&format!("'{}' is not a valid resource", &user_input)))
         } else{
             Ok(self.map.get(&tmp).expect("comparision went wrong").clone())
         }
@@ -284,7 +296,7 @@ impl ResourceMap{
             }
         }
         if !found{
-            return Err(OpenstackError::new(&format!("'{}' is not a valid resource type", &user_input)))
+            return Err(OpenstackError::config(&format!("'{}' is not a valid resource type", &user_input)))
         } else{
             Ok(self.types.get(&tmp).expect("comparision went wrong").clone())
         }
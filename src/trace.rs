@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use curl::easy::InfoType;
+use serde_json::Value as JSONValue;
+
+const REDACTED: &str = "<redacted>";
+
+const SECRET_HEADERS: [&str; 8] = [
+    "x-auth-token",
+    "x-subject-token",
+    "x-service-token",
+    "openstack-auth-receipt",
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+// matched against the lowercase field name, 'adminPass' is what nova returns for new servers
+const SECRET_FIELDS: [&str; 6] = ["token", "password", "passcode", "adminpass", "secret", "blob"];
+
+pub fn redact_header(name: &str, value: &str) -> String {
+    let name = name.trim();
+    if SECRET_HEADERS.contains(&name.to_lowercase().as_str()) {
+        return REDACTED.to_string();
+    }
+    value.trim().to_string()
+}
+
+pub fn redact_header_line(line: &str) -> String {
+    let mut split = line.splitn(2, ':');
+    match (split.next(), split.next()) {
+        // request lines such as 'GET http://proxy:8080/ HTTP/1.1' are no header
+        (Some(k), Some(v)) if !k.trim().contains(' ') => format!("{}: {}", k.trim(), redact_header(k, v)),
+        _ => line.to_string(),
+    }
+}
+
+fn is_secret_field(name: &str) -> bool {
+    let name = name.to_lowercase();
+    SECRET_FIELDS.iter().any(|x| name.contains(x))
+}
+
+pub fn redact_json(value: &JSONValue) -> JSONValue {
+    redact_json_inner(value, false)
+}
+
+fn redact_json_inner(value: &JSONValue, in_secret: bool) -> JSONValue {
+    match value {
+        JSONValue::Object(x) => x
+            .iter()
+            .map(|(k, v)| {
+                // keeps the structure of {"token": {"id": ...}} and keystone's token response,
+                // but the strings directly below a secret field are hidden
+                let secret = is_secret_field(k) | (in_secret & (k == "id"));
+                let redacted = match v {
+                    JSONValue::Object(_y) => redact_json_inner(v, secret),
+                    JSONValue::Array(_y) => redact_json_inner(v, secret),
+                    JSONValue::Null => JSONValue::Null,
+                    _ if secret => REDACTED.into(),
+                    _ => v.clone(),
+                };
+                (k.clone(), redacted)
+            })
+            .collect::<serde_json::Map<String, JSONValue>>()
+            .into(),
+        JSONValue::Array(x) => x.iter().map(|y| redact_json_inner(y, in_secret)).collect::<Vec<JSONValue>>().into(),
+        JSONValue::Null => JSONValue::Null,
+        _ if in_secret => REDACTED.into(),
+        x => x.clone(),
+    }
+}
+
+pub fn redact_body(data: &[u8]) -> String {
+    // curl hands over bodies in chunks, a part of a json body can't be redacted so only its size is shown
+    match serde_json::from_slice::<JSONValue>(data) {
+        Ok(x) => redact_json(&x).to_string(),
+        Err(_e) => format!("[{} bytes]", data.len()),
+    }
+}
+
+pub fn debug_function(kind: InfoType, data: &[u8]) {
+    // the same parts as 'curl -v', written to stderr
+    let text = String::from_utf8_lossy(data);
+    match kind {
+        InfoType::Text => eprint!("* {}", text),
+        InfoType::HeaderOut => {
+            for line in text.lines().filter(|x| !x.trim().is_empty()) {
+                eprintln!("> {}", redact_header_line(line));
+            }
+        }
+        InfoType::HeaderIn => {
+            if !text.trim().is_empty() {
+                eprintln!("< {}", redact_header_line(text.trim_end()));
+            }
+        }
+        InfoType::DataOut => eprintln!("> {}", redact_body(data)),
+        InfoType::DataIn => eprintln!("< {}", redact_body(data)),
+        _ => (),
+    }
+}
+
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+pub fn curl_command(method: &str, url: &str, headers: &HashMap<String, String>, body: Option<&JSONValue>) -> String {
+    let mut names: Vec<&String> = headers.keys().collect();
+    names.sort();
+    let mut command = format!("curl -X {} {}", method.to_uppercase(), shell_quote(url));
+    for name in names {
+        let header = format!("{}: {}", name, redact_header(name, &headers[name]));
+        command = format!("{} -H {}", command, shell_quote(&header));
+    }
+    if let Some(x) = body {
+        command = format!(
+            "{} -H 'Content-Type: application/json' -d {}",
+            command,
+            shell_quote(&redact_json(x).to_string())
+        );
+    }
+    command
+}
+
+pub fn log_request(method: &str, url: &str, headers: &HashMap<String, String>, body: Option<&JSONValue>) {
+    eprintln!("* {}", curl_command(method, url, headers, body));
+}
+
+pub fn log_finished(method: &str, url: &str, status: Option<u32>, elapsed: Duration) {
+    let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_millis()) / 1000.0;
+    match status {
+        Some(x) => eprintln!("* {} {} returned {} in {:.3}s", method.to_uppercase(), url, x, seconds),
+        None => eprintln!("* {} {} failed after {:.3}s", method.to_uppercase(), url, seconds),
+    }
+}
+
+#[test]
+fn test_redact_auth_body() {
+    let body = json!({"auth": {
+        "identity": {
+            "methods": ["password", "token"],
+            "password": {"user": {"name": "admin", "password": "hunter2", "domain": {"id": "default"}}},
+            "token": {"id": "gAAAA"}
+        },
+        "scope": {"project": {"id": "1234"}}
+    }});
+    let redacted = redact_json(&body);
+    assert_eq!(redacted["auth"]["identity"]["password"]["user"]["password"], "<redacted>");
+    assert_eq!(redacted["auth"]["identity"]["password"]["user"]["name"], "admin");
+    assert_eq!(redacted["auth"]["identity"]["token"]["id"], "<redacted>");
+    assert_eq!(redacted["auth"]["identity"]["methods"], json!(["password", "token"]));
+    assert_eq!(redacted["auth"]["scope"]["project"]["id"], "1234");
+
+    let credential = json!({"credential": {"blob": "{\"access\": \"a\"}", "type": "ec2"}});
+    assert_eq!(redact_json(&credential)["credential"]["blob"], "<redacted>");
+    let server = json!({"server": {"id": "1", "adminPass": "secret"}});
+    assert_eq!(redact_json(&server), json!({"server": {"id": "1", "adminPass": "<redacted>"}}));
+}
+
+#[test]
+fn test_redact_headers_and_curl_command() {
+    assert_eq!(redact_header_line("X-Auth-Token: gAAAA"), "X-Auth-Token: <redacted>");
+    assert_eq!(redact_header_line("x-subject-token:gAAAA"), "x-subject-token: <redacted>");
+    assert_eq!(redact_header_line("Location: https://example.com:8774/"), "Location: https://example.com:8774/");
+    assert_eq!(redact_header_line("GET http://proxy:8080/v2.1 HTTP/1.1"), "GET http://proxy:8080/v2.1 HTTP/1.1");
+
+    let mut headers = HashMap::new();
+    headers.insert("X-Auth-Token".to_string(), "gAAAA".to_string());
+    assert_eq!(
+        curl_command("post", "https://example.com/servers?name=it's", &headers, Some(&json!({"server": {"name": "a"}}))),
+        "curl -X POST 'https://example.com/servers?name=it'\\''s' -H 'X-Auth-Token: <redacted>' \
+         -H 'Content-Type: application/json' -d '{\"server\":{\"name\":\"a\"}}'"
+    );
+    assert_eq!(redact_body(&[0u8; 2048]), "[2048 bytes]");
+    assert_eq!(redact_body(br#"{"user": {"name": "a", "password": "hunter2"}}"#), r#"{"user":{"name":"a","password":"<redacted>"}}"#);
+    // the last chunk of a body that was split by curl
+    assert_eq!(redact_body(br#"ain", "password": "hunter2"}}}"#), "[30 bytes]");
+    assert_eq!(redact_body(b"<html>Service Unavailable</html>"), "[32 bytes]");
+}
@@ -2,4 +2,10 @@ pub trait SerdeList{
     fn items(&self) -> Vec<(String, serde_json::Value)>;
     fn keys(&self) -> Vec<String>;
     fn values(&self) -> Vec<serde_json::Value>;
-}
\ No newline at end of file
+}
+
+pub trait SecretStore{
+    fn get(&self, key: &str) -> Result<Option<String>, std::io::Error>;
+    fn set(&self, key: &str, secret: &str) -> Result<(), std::io::Error>;
+    fn delete(&self, key: &str) -> Result<(), std::io::Error>;
+}
@@ -3,8 +3,12 @@ use std::io::{Error, ErrorKind};
 use yaml_rust::yaml;
 
 use prettytable::Table;
+use std::cmp::Ordering;
 use std::collections::HashSet;
 
+use pagination::{merge_pages, split_page};
+use query::Query;
+
 #[allow(dead_code)]
 pub fn convert_to_singular(tmp: &str) -> &str {
     // not 100% bulletproof but good enough for matching
@@ -107,6 +111,43 @@ pub fn read_yaml(location: String) -> Result<serde_yaml::Value, Error> {
     Ok(value)
 }
 
+pub fn merge_yaml(a: &mut serde_yaml::Value, b: &serde_yaml::Value) {
+    // deep merges b into a, values from b win
+    match (a, b) {
+        (&mut serde_yaml::Value::Mapping(ref mut a), serde_yaml::Value::Mapping(ref b)) => {
+            for (k, v) in b {
+                if let Some(x) = a.get_mut(k) {
+                    merge_yaml(x, v);
+                    continue;
+                }
+                a.insert(k.clone(), v.clone());
+            }
+        }
+        (a, b) => *a = b.clone(),
+    }
+}
+
+pub fn user_config_dir() -> Option<std::path::PathBuf> {
+    // same locations as the python openstack clients use
+    if cfg!(windows) {
+        return std::env::var_os("APPDATA").map(std::path::PathBuf::from);
+    }
+    if let Some(x) = std::env::var_os("XDG_CONFIG_HOME") {
+        return Some(std::path::PathBuf::from(x));
+    }
+    std::env::var_os("HOME").map(|x| std::path::PathBuf::from(x).join(".config"))
+}
+
+pub fn user_cache_dir() -> Option<std::path::PathBuf> {
+    if cfg!(windows) {
+        return std::env::var_os("LOCALAPPDATA").map(std::path::PathBuf::from);
+    }
+    if let Some(x) = std::env::var_os("XDG_CACHE_HOME") {
+        return Some(std::path::PathBuf::from(x));
+    }
+    std::env::var_os("HOME").map(|x| std::path::PathBuf::from(x).join(".cache"))
+}
+
 #[allow(dead_code)]
 pub fn read_yaml_rust(location: String) -> yaml::Yaml {
     let s = read_yaml(location).unwrap();
@@ -245,20 +286,347 @@ fn merge(a: &mut serde_json::Value, b: &serde_json::Value) {
     }
 }
 
-pub fn print_value(v: &serde_json::Value, f: &str) {
-    let txt = match f {
-        "json" => serde_json::to_string_pretty(v).unwrap(),
-        "csv" => convert_to_csv(v),
-        "table" => {
-            let a_csv = convert_to_csv(v);
-            let mut table = Table::from_csv_string(&a_csv).unwrap();
-            table.set_format(*prettytable::format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
-            format!("{}", table)
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputOptions {
+    // dotted paths such as 'addresses.private.0.addr'
+    pub columns: Vec<String>,
+    pub sort_by: Option<String>,
+    pub reverse: bool,
+    // only items where the path has this value are kept
+    pub filters: Vec<(String, String)>,
+    // a jmespath expression, evaluated before everything else
+    pub query: Option<Query>,
+    // put before every variable name of the shell format
+    pub prefix: String,
+}
+
+// keeps response fields from overwriting variables like PATH when the shell output is evaluated
+pub const DEFAULT_SHELL_PREFIX: &str = "os_";
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            columns: vec![],
+            sort_by: None,
+            reverse: false,
+            filters: vec![],
+            query: None,
+            prefix: DEFAULT_SHELL_PREFIX.to_string(),
+        }
+    }
+}
+
+impl OutputOptions {
+    pub fn from_matches(matches: &clap::ArgMatches) -> Result<Self, String> {
+        let mut filters = vec![];
+        for x in matches.values_of("filter").into_iter().flatten() {
+            let mut split = x.splitn(2, '=');
+            match (split.next(), split.next()) {
+                (Some(k), Some(v)) if !k.is_empty() => filters.push((k.to_string(), v.to_string())),
+                _ => return Err(format!("'{}' is not a valid filter, use key=value", x)),
+            }
+        }
+        let columns = matches
+            .value_of("columns")
+            .map(|x| x.split(',').map(|y| y.trim().to_string()).filter(|y| !y.is_empty()).collect())
+            .unwrap_or_default();
+        let query = match matches.value_of("query") {
+            Some(x) => Some(Query::new(x)?),
+            None => None,
+        };
+        let prefix = matches.value_of("prefix").unwrap_or(DEFAULT_SHELL_PREFIX);
+        if shell_name(prefix) != prefix {
+            return Err(format!("'{}' is not a valid prefix for shell variables", prefix));
+        }
+        Ok(OutputOptions {
+            columns,
+            sort_by: matches.value_of("sort-by").map(|x| x.to_string()),
+            reverse: matches.is_present("reverse"),
+            filters,
+            query,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    pub fn apply(&self, value: &serde_json::Value) -> Result<serde_json::Value, String> {
+        match &self.query {
+            Some(x) => Ok(self.reshape(&x.search(value)?)),
+            None => Ok(self.reshape(value)),
+        }
+    }
+
+    pub fn needs_whole_list(&self) -> bool {
+        // pages that are printed one by one can't be sorted or queried as a whole
+        self.sort_by.is_some() | self.reverse | self.query.is_some()
+    }
+
+    fn reshape(&self, value: &serde_json::Value) -> serde_json::Value {
+        if let Some((key, items)) = split_page(value) {
+            let mut items: Vec<serde_json::Value> = items.into_iter().filter(|x| self.keeps(x)).collect();
+            match &self.sort_by {
+                Some(x) => {
+                    // items without the field stay last, also when reversed
+                    let (mut present, missing): (Vec<serde_json::Value>, Vec<serde_json::Value>) =
+                        items.into_iter().partition(|y| get_path(y, x).map_or(false, |z| !z.is_null()));
+                    present.sort_by(|a, b| compare_values(get_path(a, x), get_path(b, x)));
+                    if self.reverse {
+                        present.reverse();
+                    }
+                    present.extend(missing);
+                    items = present;
+                }
+                None if self.reverse => items.reverse(),
+                None => (),
+            }
+            let items = items.iter().map(|x| self.select(x)).collect();
+            return merge_pages(value, &key, items);
+        }
+        // a single resource such as {"server": {...}}
+        match single_resource(value) {
+            Some((key, item)) => json!({ key: self.select(item) }),
+            None => self.select(value),
+        }
+    }
+
+    fn keeps(&self, item: &serde_json::Value) -> bool {
+        self.filters
+            .iter()
+            .all(|(k, v)| get_path(item, k).map_or(false, |x| &scalar_to_string(x) == v))
+    }
+
+    fn select(&self, item: &serde_json::Value) -> serde_json::Value {
+        if self.columns.is_empty() | !item.is_object() {
+            return item.clone();
         }
-        _ => String::from(""),
+        self.columns
+            .iter()
+            .map(|x| (x.clone(), get_path(item, x).cloned().unwrap_or(serde_json::Value::Null)))
+            .collect::<serde_json::Map<String, serde_json::Value>>()
+            .into()
+    }
+}
+
+pub fn get_path<'a>(value: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    // keys that contain a dot themselves are matched as a whole first
+    if let Some(x) = value.get(path) {
+        return Some(x);
+    }
+    let mut split = path.splitn(2, '.');
+    let first = split.next()?;
+    let next = match value {
+        serde_json::Value::Array(x) => x.get(first.parse::<usize>().ok()?)?,
+        _ => value.get(first)?,
+    };
+    match split.next() {
+        Some(x) => get_path(next, x),
+        None => Some(next),
+    }
+}
+
+fn single_resource(value: &serde_json::Value) -> Option<(&String, &serde_json::Value)> {
+    let object = value.as_object()?;
+    match object.iter().next() {
+        Some((k, v)) if (object.len() == 1) && v.is_object() => Some((k, v)),
+        _ => None,
+    }
+}
+
+fn scalar_to_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(x) => x.clone(),
+        serde_json::Value::Null => String::new(),
+        x => x.to_string(),
+    }
+}
+
+fn compare_values(a: Option<&serde_json::Value>, b: Option<&serde_json::Value>) -> Ordering {
+    // missing values go last
+    let a = a.filter(|x| !x.is_null());
+    let b = b.filter(|x| !x.is_null());
+    match (a, b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_y)) => Ordering::Greater,
+        (Some(_x), None) => Ordering::Less,
+        (Some(x), Some(y)) => match (x.as_f64(), y.as_f64()) {
+            (Some(i), Some(j)) => i.partial_cmp(&j).unwrap_or(Ordering::Equal),
+            _ => scalar_to_string(x).cmp(&scalar_to_string(y)),
+        },
+    }
+}
+
+pub fn print_output(v: &serde_json::Value, f: &str, options: &OutputOptions) -> Result<(), String> {
+    let txt = match formatter(f) {
+        Some(x) => x.format(v, options)?,
+        None => String::from(""),
     };
 
     println!("{}", txt);
+    Ok(())
+}
+
+// the names accepted by '--format', every name has a formatter
+pub const OUTPUT_FORMATS: [&str; 7] = ["json", "csv", "table", "yaml", "value", "shell", "ndjson"];
+
+pub trait OutputFormatter {
+    fn format(&self, value: &serde_json::Value, options: &OutputOptions) -> Result<String, String>;
+}
+
+pub fn formatter(name: &str) -> Option<Box<dyn OutputFormatter>> {
+    match name {
+        "json" => Some(Box::new(JsonFormatter)),
+        "csv" => Some(Box::new(CsvFormatter)),
+        "table" => Some(Box::new(TableFormatter)),
+        "yaml" => Some(Box::new(YamlFormatter)),
+        "value" => Some(Box::new(ValueFormatter)),
+        "shell" => Some(Box::new(ShellFormatter)),
+        "ndjson" => Some(Box::new(NdjsonFormatter)),
+        _ => None,
+    }
+}
+
+pub struct JsonFormatter;
+pub struct CsvFormatter;
+pub struct TableFormatter;
+pub struct YamlFormatter;
+pub struct ValueFormatter;
+pub struct ShellFormatter;
+pub struct NdjsonFormatter;
+
+impl OutputFormatter for JsonFormatter {
+    fn format(&self, value: &serde_json::Value, _options: &OutputOptions) -> Result<String, String> {
+        Ok(serde_json::to_string_pretty(value).unwrap())
+    }
+}
+
+impl OutputFormatter for CsvFormatter {
+    fn format(&self, value: &serde_json::Value, options: &OutputOptions) -> Result<String, String> {
+        match options.columns.is_empty() {
+            true => Ok(convert_to_csv(value)),
+            false => Ok(columns_to_csv(value, &options.columns)),
+        }
+    }
+}
+
+impl OutputFormatter for TableFormatter {
+    fn format(&self, value: &serde_json::Value, options: &OutputOptions) -> Result<String, String> {
+        let a_csv = CsvFormatter.format(value, options)?;
+        let mut table = Table::from_csv_string(&a_csv).unwrap();
+        table.set_format(*prettytable::format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+        Ok(format!("{}", table))
+    }
+}
+
+impl OutputFormatter for YamlFormatter {
+    fn format(&self, value: &serde_json::Value, _options: &OutputOptions) -> Result<String, String> {
+        Ok(serde_yaml::to_string(value).unwrap())
+    }
+}
+
+impl OutputFormatter for ValueFormatter {
+    // like 'openstack -f value': a line per list item with its values separated by spaces,
+    // or a line per field for a single resource
+    fn format(&self, value: &serde_json::Value, _options: &OutputOptions) -> Result<String, String> {
+        let lines: Vec<String> = match split_page(value) {
+            Some((_k, items)) => items.iter().map(value_line).collect(),
+            None => match single_resource(value).map(|x| x.1).unwrap_or(value) {
+                serde_json::Value::Object(x) => x.values().map(bare_value).collect(),
+                x => vec![value_line(x)],
+            },
+        };
+        Ok(lines.join("\n"))
+    }
+}
+
+fn value_line(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Object(x) => x.values().map(bare_value).collect::<Vec<String>>().join(" "),
+        serde_json::Value::Array(x) => x.iter().map(bare_value).collect::<Vec<String>>().join(" "),
+        x => bare_value(x),
+    }
+}
+
+fn bare_value(value: &serde_json::Value) -> String {
+    // strings without quotes, nested values stay json
+    match value {
+        serde_json::Value::Object(_x) => value.to_string(),
+        serde_json::Value::Array(_x) => value.to_string(),
+        x => scalar_to_string(x),
+    }
+}
+
+impl OutputFormatter for ShellFormatter {
+    // nested fields are joined with '_', so 'servers.0.id' becomes os_servers_0_id="..."
+    fn format(&self, value: &serde_json::Value, options: &OutputOptions) -> Result<String, String> {
+        let mut variables = vec![];
+        match single_resource(value).map(|x| x.1).unwrap_or(value) {
+            x if x.is_object() | x.is_array() => shell_variables("", x, &mut variables),
+            x => shell_variables("value", x, &mut variables),
+        }
+        let mut names: HashMap<String, String> = HashMap::new();
+        let mut lines = vec![];
+        for (path, text) in variables {
+            let name = shell_name(&format!("{}{}", options.prefix, path));
+            // 'a-b' and 'a_b' would silently overwrite each other
+            if let Some(x) = names.insert(name.clone(), path.clone()) {
+                return Err(format!("the fields '{}' and '{}' are both written as the shell variable {}", x, path, name));
+            }
+            lines.push(format!("{}=\"{}\"", name, shell_escape(&text)));
+        }
+        Ok(lines.join("\n"))
+    }
+}
+
+fn shell_variables(path: &str, value: &serde_json::Value, variables: &mut Vec<(String, String)>) {
+    let join = |x: &str| match path {
+        "" => x.to_string(),
+        _ => format!("{}_{}", path, x),
+    };
+    match value {
+        serde_json::Value::Object(x) => {
+            for (k, v) in x {
+                shell_variables(&join(k), v, variables);
+            }
+        }
+        serde_json::Value::Array(x) => {
+            for (i, v) in x.iter().enumerate() {
+                shell_variables(&join(&i.to_string()), v, variables);
+            }
+        }
+        x => variables.push((path.to_string(), scalar_to_string(x))),
+    }
+}
+
+fn shell_name(key: &str) -> String {
+    // variable names may only contain letters, digits and underscores and cannot start with a digit
+    let name: String = key
+        .chars()
+        .map(|x| if x.is_ascii_alphanumeric() { x } else { '_' })
+        .collect();
+    match name.chars().next() {
+        Some(x) if x.is_ascii_digit() => format!("_{}", name),
+        _ => name,
+    }
+}
+
+fn shell_escape(text: &str) -> String {
+    // these are the only characters that are special between double quotes
+    let mut escaped = String::with_capacity(text.len());
+    for x in text.chars() {
+        if let '\\' | '"' | '$' | '`' = x {
+            escaped.push('\\');
+        }
+        escaped.push(x);
+    }
+    escaped
+}
+
+impl OutputFormatter for NdjsonFormatter {
+    fn format(&self, value: &serde_json::Value, _options: &OutputOptions) -> Result<String, String> {
+        match split_page(value) {
+            Some((_k, items)) => Ok(items.iter().map(|x| x.to_string()).collect::<Vec<String>>().join("\n")),
+            None => Ok(value.to_string()),
+        }
+    }
 }
 
 fn convert_to_csv(v: &serde_json::Value) -> String {
@@ -284,6 +652,32 @@ fn convert_to_csv(v: &serde_json::Value) -> String {
     }
 }
 
+fn columns_to_csv(v: &serde_json::Value, columns: &[String]) -> String {
+    // keeps the order of the columns, nested values are written as json
+    let rows = match split_page(v) {
+        Some((_k, items)) => items,
+        None => match single_resource(v) {
+            Some((_k, item)) => vec![item.clone()],
+            None => vec![v.clone()],
+        },
+    };
+    let block_data = rows
+        .iter()
+        .map(|row| {
+            columns
+                .iter()
+                .map(|x| match row.get(x).or_else(|| get_path(row, x)) {
+                    Some(serde_json::Value::Object(y)) => serde_json::to_string(y).unwrap().into(),
+                    Some(serde_json::Value::Array(y)) => serde_json::to_string(y).unwrap().into(),
+                    Some(y) => scalar_to_string(y).into(),
+                    None => serde_json::Value::String("".into()),
+                })
+                .collect()
+        })
+        .collect();
+    _write_a_csv(columns.to_vec(), block_data)
+}
+
 fn _write_a_csv(sorted_headers: Vec<String>, block_data: Vec<Vec<serde_json::Value>>) -> String {
     let mut wtr = csv::Writer::from_writer(vec![]);
     wtr.write_record(sorted_headers).unwrap();
@@ -494,6 +888,40 @@ fn test_merge_values_3() {
     assert_eq!(merge_values(&a, &b), c);
 }
 
+#[test]
+fn test_merge_yaml() {
+    let mut a: serde_yaml::Value = serde_yaml::from_str(r#"
+    clouds:
+      cloud:
+        auth:
+          auth_url: "https://identity.example.com"
+          username: "test_user"
+        region_name: "test"
+    "#).unwrap();
+    let b: serde_yaml::Value = serde_yaml::from_str(r#"
+    clouds:
+      cloud:
+        auth:
+          password: "secret_password"
+        region_name: "other"
+      cloud2:
+        region_name: "test"
+    "#).unwrap();
+    let c: serde_yaml::Value = serde_yaml::from_str(r#"
+    clouds:
+      cloud:
+        auth:
+          auth_url: "https://identity.example.com"
+          username: "test_user"
+          password: "secret_password"
+        region_name: "other"
+      cloud2:
+        region_name: "test"
+    "#).unwrap();
+    merge_yaml(&mut a, &b);
+    assert_eq!(a, c);
+}
+
 #[test]
 fn test_hashmaps_from_dot_notation() {
     let listing = vec![
@@ -734,4 +1162,157 @@ fn test_remove_fields(){
     remove_empty_fields(&mut data);
 
     assert_eq!(data, expected_output);
-}
\ No newline at end of file
+}
+#[test]
+fn test_output_options_filter_sort_and_columns() {
+    let servers = json!({"servers": [
+        {"id": "1", "name": "b", "status": "ACTIVE", "addresses": {"private": [{"addr": "10.0.0.1"}]}},
+        {"id": "2", "name": "a", "status": "ERROR", "addresses": {}},
+        {"id": "3", "name": "c", "status": "ACTIVE", "addresses": {"private": [{"addr": "10.0.0.3"}]}}
+    ]});
+    let options = OutputOptions {
+        columns: vec!["id".to_string(), "addresses.private.0.addr".to_string()],
+        sort_by: Some("name".to_string()),
+        reverse: true,
+        filters: vec![("status".to_string(), "ACTIVE".to_string())],
+        ..Default::default()
+    };
+    let output = options.apply(&servers).unwrap();
+    assert_eq!(
+        output,
+        json!({"servers": [
+            {"id": "3", "addresses.private.0.addr": "10.0.0.3"},
+            {"id": "1", "addresses.private.0.addr": "10.0.0.1"}
+        ]})
+    );
+    assert_eq!(columns_to_csv(&output, &options.columns), "id,addresses.private.0.addr\n3,10.0.0.3\n1,10.0.0.1\n");
+
+    let server = json!({"server": {"id": "1", "name": "a", "metadata": {"k": "v"}}});
+    let options = OutputOptions {
+        columns: vec!["name".to_string(), "metadata".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(options.apply(&server).unwrap(), json!({"server": {"name": "a", "metadata": {"k": "v"}}}));
+    assert_eq!(columns_to_csv(&server, &options.columns), "name,metadata\na,\"{\"\"k\"\":\"\"v\"\"}\"\n");
+}
+
+#[test]
+fn test_sort_by_numbers_and_missing_values() {
+    let volumes = json!([{"size": 10}, {"name": "x"}, {"size": 2}]);
+    let options = OutputOptions {
+        sort_by: Some("size".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(options.apply(&volumes).unwrap(), json!([{"size": 2}, {"size": 10}, {"name": "x"}]));
+    let options = OutputOptions {
+        sort_by: Some("size".to_string()),
+        reverse: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        options.apply(&json!([{"size": 10}, {"size": null}, {"name": "x"}, {"size": 2}])).unwrap(),
+        json!([{"size": 10}, {"size": 2}, {"size": null}, {"name": "x"}])
+    );
+    assert!(options.needs_whole_list());
+    assert!(!OutputOptions::default().needs_whole_list());
+    assert_eq!(get_path(&json!({"a": [1, {"b": true}]}), "a.1.b"), Some(&json!(true)));
+    assert_eq!(get_path(&json!({"a": [1]}), "a.x"), None);
+}
+
+#[test]
+fn test_query_runs_before_the_other_options() {
+    let servers = json!({"servers": [
+        {"id": "1", "name": "b", "status": "ACTIVE"},
+        {"id": "2", "name": "a", "status": "ERROR"},
+        {"id": "3", "name": "c", "status": "ACTIVE"}
+    ]});
+    let options = OutputOptions {
+        columns: vec!["Name".to_string()],
+        sort_by: Some("Name".to_string()),
+        query: Some(Query::new("servers[?status=='ACTIVE'].{ID: id, Name: name}").unwrap()),
+        ..Default::default()
+    };
+    assert_eq!(options.apply(&servers).unwrap(), json!([{"Name": "b"}, {"Name": "c"}]));
+
+    let options = OutputOptions {
+        query: Some(Query::new("servers[0].name").unwrap()),
+        ..Default::default()
+    };
+    assert_eq!(options.apply(&servers).unwrap(), json!("b"));
+
+    let options = OutputOptions {
+        query: Some(Query::new("length(servers[0].id.missing)").unwrap()),
+        ..Default::default()
+    };
+    assert!(options.apply(&servers).is_err());
+}
+
+#[test]
+fn test_every_output_format_has_a_formatter() {
+    for x in OUTPUT_FORMATS.iter() {
+        assert!(formatter(x).is_some(), "{} has no formatter", x);
+    }
+    assert!(formatter("xml").is_none());
+}
+
+#[test]
+fn test_yaml_value_and_ndjson_formats() {
+    let options = OutputOptions::default();
+    let servers = json!({"servers": [{"id": "1", "name": "a"}, {"id": "2", "name": "b", "tags": ["x", "y"]}]});
+    let server = json!({"server": {"id": "1", "name": "a", "metadata": {"k": "v"}}});
+
+    let yaml: serde_json::Value = serde_yaml::from_str(&YamlFormatter.format(&servers, &options).unwrap()).unwrap();
+    assert_eq!(yaml, servers);
+
+    assert_eq!(ValueFormatter.format(&servers, &options).unwrap(), "1 a\n2 b [\"x\",\"y\"]");
+    assert_eq!(ValueFormatter.format(&server, &options).unwrap(), "1\n{\"k\":\"v\"}\na");
+    assert_eq!(ValueFormatter.format(&json!(["a", "b"]), &options).unwrap(), "a\nb");
+    assert_eq!(ValueFormatter.format(&json!("a"), &options).unwrap(), "a");
+
+    assert_eq!(
+        NdjsonFormatter.format(&servers, &options).unwrap(),
+        "{\"id\":\"1\",\"name\":\"a\"}\n{\"id\":\"2\",\"name\":\"b\",\"tags\":[\"x\",\"y\"]}"
+    );
+    assert_eq!(NdjsonFormatter.format(&server, &options).unwrap(), server.to_string());
+}
+
+#[test]
+fn test_shell_format_can_be_evaluated() {
+    let options = OutputOptions::default();
+    let server = json!({"server": {
+        "id": "1",
+        "name": "a \"quoted\" $(rm -rf /) `x` \\ name",
+        "OS-EXT-STS:vm_state": "active",
+        "addresses": {"private": [{"addr": "10.0.0.1"}]},
+        "image": null
+    }});
+    assert_eq!(
+        ShellFormatter.format(&server, &options).unwrap(),
+        "os_OS_EXT_STS_vm_state=\"active\"\n\
+         os_addresses_private_0_addr=\"10.0.0.1\"\n\
+         os_id=\"1\"\n\
+         os_image=\"\"\n\
+         os_name=\"a \\\"quoted\\\" \\$(rm -rf /) \\`x\\` \\\\ name\""
+    );
+    assert_eq!(ShellFormatter.format(&json!(["a"]), &options).unwrap(), "os_0=\"a\"");
+    assert_eq!(ShellFormatter.format(&json!(3), &options).unwrap(), "os_value=\"3\"");
+
+    let options = OutputOptions {
+        prefix: String::new(),
+        ..Default::default()
+    };
+    assert_eq!(ShellFormatter.format(&json!(["a"]), &options).unwrap(), "_0=\"a\"");
+    assert_eq!(ShellFormatter.format(&json!({"PATH": "/x"}), &options).unwrap(), "PATH=\"/x\"");
+}
+
+#[test]
+fn test_shell_format_rejects_duplicate_names() {
+    let options = OutputOptions::default();
+    let server = json!({"server": {"a-b": "1", "a_b": "2"}});
+    assert_eq!(
+        ShellFormatter.format(&server, &options).unwrap_err(),
+        "the fields 'a-b' and 'a_b' are both written as the shell variable os_a_b"
+    );
+    let server = json!({"server": {"a": {"b": "1"}, "a_b": "2"}});
+    assert!(ShellFormatter.format(&server, &options).is_err());
+}
@@ -1,6 +1,7 @@
 extern crate serde_json;
+extern crate serde_yaml;
 mod common;
-use common::{create_cmd, get_stdout, make_args, Output};
+use common::{create_cmd, create_noauth_cmd, get_stdout, make_args, recording, Output};
 
 extern crate pest;
 #[macro_use]
@@ -86,3 +87,366 @@ fn create_keypair() {
     let output = Output::from_stdout(&raw_output);
     assert_eq!(expected, output);
 }
+
+#[test]
+fn explicit_cloud_with_invalid_config_exits_with_config_error() {
+    let clouds_yaml = format!("{}/tests/configs/clouds.yaml", env!("CARGO_MANIFEST_DIR"));
+    let expected = vec![
+        ("vendor", "error: cloud 'vendor': profile 'unknown-vendor' not found in clouds-public.yaml\n"),
+        ("missing", "error: cloud 'missing': not found in clouds.yaml\n"),
+    ];
+    for (cloud, message) in expected {
+        let mut cmd = create_noauth_cmd(vec![("OS_CLIENT_CONFIG_FILE", &clouds_yaml)]);
+        let output = cmd
+            .args(&["--os-cloud", cloud, "-f", "csv"])
+            .args(make_args(vec!["list", "servers"]))
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), message);
+    }
+}
+
+#[test]
+fn list_servers_with_endpoint_override() {
+    let expected = Output::new(
+        "GET",
+        "http://localhost:8774/v2.1/servers?",
+        json!({"x-auth-token": "token"}),
+        json!(null),
+    );
+
+    let mut cmd = create_cmd();
+    cmd.env("OS_COMPUTE_ENDPOINT_OVERRIDE", "http://localhost:8774/v2.1");
+    let raw_output = get_stdout(cmd.args(make_args(vec!["list", "servers"])));
+    let output = Output::from_stdout(&raw_output);
+    assert_eq!(expected, output);
+}
+
+#[test]
+fn list_servers_without_auth() {
+    let expected = Output::new(
+        "GET",
+        "http://localhost:8774/v2.1/servers?",
+        json!({}),
+        json!(null),
+    );
+
+    let mut cmd = create_noauth_cmd(vec![("OS_COMPUTE_ENDPOINT_OVERRIDE", "http://localhost:8774/v2.1")]);
+    let raw_output = get_stdout(cmd.args(make_args(vec!["list", "servers"])));
+    let output = Output::from_stdout(&raw_output);
+    assert_eq!(expected, output);
+}
+
+#[test]
+fn list_networks_adds_default_version() {
+    let expected = Output::new(
+        "GET",
+        "http://localhost:9696/v2.0/networks?",
+        json!({}),
+        json!(null),
+    );
+
+    let mut cmd = create_noauth_cmd(vec![("OS_NETWORK_ENDPOINT_OVERRIDE", "http://localhost:9696")]);
+    let raw_output = get_stdout(cmd.args(make_args(vec!["list", "networks"])));
+    let output = Output::from_stdout(&raw_output);
+    assert_eq!(expected, output);
+}
+
+#[test]
+fn list_volumes_from_block_storage() {
+    let expected = Output::new(
+        "GET",
+        "http://localhost:8776/v3/1234/volumes?",
+        json!({}),
+        json!(null),
+    );
+
+    let mut cmd = create_noauth_cmd(vec![("OS_BLOCK_STORAGE_ENDPOINT_OVERRIDE", "http://localhost:8776/v3/1234")]);
+    let raw_output = get_stdout(cmd.args(make_args(vec!["list", "volumes"])));
+    let output = Output::from_stdout(&raw_output);
+    assert_eq!(expected, output);
+}
+
+#[test]
+fn list_servers_with_compute_microversion() {
+    let expected = Output::new(
+        "GET",
+        "http://localhost:8774/v2.1/servers?",
+        json!({"X-OpenStack-Nova-API-Version": "2.53"}),
+        json!(null),
+    );
+
+    let mut cmd = create_noauth_cmd(vec![("OS_COMPUTE_ENDPOINT_OVERRIDE", "http://localhost:8774/v2.1")]);
+    cmd.args(&["--os-compute-api-version", "2.53"]);
+    let raw_output = get_stdout(cmd.args(make_args(vec!["list", "servers"])));
+    let output = Output::from_stdout(&raw_output);
+    assert_eq!(expected, output);
+}
+
+#[test]
+fn microversion_options_work_after_the_subcommand() {
+    let expected = Output::new(
+        "GET",
+        "http://localhost:8774/v2.1/servers/1234?",
+        json!({"X-OpenStack-Nova-API-Version": "2.60"}),
+        json!(null),
+    );
+
+    let mut cmd = create_noauth_cmd(vec![("OS_COMPUTE_ENDPOINT_OVERRIDE", "http://localhost:8774/v2.1")]);
+    let raw_output = get_stdout(cmd.args(make_args(vec!["get", "servers", "1234", "--os-compute-api-version", "2.60"])));
+    assert_eq!(expected, Output::from_stdout(&raw_output));
+
+    // the deprecated servers option sets the same header
+    let mut cmd = create_noauth_cmd(vec![("OS_COMPUTE_ENDPOINT_OVERRIDE", "http://localhost:8774/v2.1")]);
+    let output = cmd.args(make_args(vec!["get", "servers", "1234", "--microversion", "2.60"])).output().unwrap();
+    assert_eq!(expected, Output::from_stdout(&String::from_utf8(output.stdout).unwrap()));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "warning: --microversion is deprecated, use --os-compute-api-version\n"
+    );
+}
+
+#[test]
+fn list_volumes_with_volume_microversion() {
+    let expected = Output::new(
+        "GET",
+        "http://localhost:8776/v3/1234/volumes?",
+        json!({"OpenStack-API-Version": "volume 3.59"}),
+        json!(null),
+    );
+
+    let mut cmd = create_noauth_cmd(vec![
+        ("OS_VOLUMEV3_ENDPOINT_OVERRIDE", "http://localhost:8776/v3/1234"),
+        ("OS_VOLUME_API_VERSION", "3.59"),
+    ]);
+    let raw_output = get_stdout(cmd.args(make_args(vec!["list", "volumes"])));
+    let output = Output::from_stdout(&raw_output);
+    assert_eq!(expected, output);
+}
+
+#[test]
+fn new_server_raises_microversion_for_parameter() {
+    let expected = Output::new(
+        "POST",
+        "http://localhost:8774/v2.1/servers?",
+        json!({"X-OpenStack-Nova-API-Version": "2.19"}),
+        json!({"server": {"name": "test", "flavorRef": "small", "description": "testing", "networks": []}}),
+    );
+
+    let mut cmd = create_noauth_cmd(vec![("OS_COMPUTE_ENDPOINT_OVERRIDE", "http://localhost:8774/v2.1")]);
+    let raw_output = get_stdout(cmd.args(make_args(vec![
+        "new", "servers", "--name", "test", "--flavor", "small", "--description", "testing",
+    ])));
+    let output = Output::from_stdout(&raw_output);
+    assert_eq!(expected, output);
+}
+
+#[test]
+fn list_servers_with_limit_and_marker() {
+    let expected = Output::new(
+        "GET",
+        "http://localhost:8774/v2.1/servers?limit=5&marker=abc",
+        json!({}),
+        json!(null),
+    );
+
+    let mut cmd = create_noauth_cmd(vec![("OS_COMPUTE_ENDPOINT_OVERRIDE", "http://localhost:8774/v2.1")]);
+    let raw_output = get_stdout(cmd.args(make_args(vec![
+        "list", "--limit", "5", "--marker", "abc", "servers",
+    ])));
+    let output = Output::from_stdout(&raw_output);
+    assert_eq!(expected, output);
+}
+
+#[test]
+fn unavailable_endpoint_exits_with_error_on_stderr() {
+    let mut cmd = create_noauth_cmd(vec![]);
+    let output = cmd
+        .args(&["--format", "json"])
+        .args(make_args(vec!["list", "servers"]))
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(
+        error,
+        json!({"error": {
+            "kind": "config",
+            "message": "endpoint for resource 'servers' is not available",
+            "exit_code": 2
+        }})
+    );
+}
+
+#[test]
+fn list_servers_follows_recorded_pages() {
+    let mut cmd = create_noauth_cmd(vec![("OS_COMPUTE_ENDPOINT_OVERRIDE", "https://compute.example.com/v2.1")]);
+    let output = cmd
+        .args(&["--replay", &recording("list_servers")])
+        .args(&["list", "servers"])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    let servers: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        servers,
+        json!({"servers": [
+            {"id": "1", "name": "server-1"},
+            {"id": "2", "name": "server-2"},
+            {"id": "3", "name": "server-3"},
+            {"id": "4", "name": "server-4"},
+            {"id": "5", "name": "server-5"}
+        ]})
+    );
+}
+
+#[test]
+fn list_servers_stops_when_a_page_repeats() {
+    let mut cmd = create_noauth_cmd(vec![("OS_COMPUTE_ENDPOINT_OVERRIDE", "https://compute.example.com/v2.1")]);
+    let output = cmd
+        .args(&["--replay", &recording("list_servers_loop"), "-f", "csv"])
+        .args(&["list", "servers"])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "error: page https://compute.example.com/v2.1/servers?marker=1 was already fetched, the listing does not advance\n"
+    );
+}
+
+#[test]
+fn get_missing_server_from_recording() {
+    let mut cmd = create_noauth_cmd(vec![("OS_COMPUTE_ENDPOINT_OVERRIDE", "https://compute.example.com/v2.1")]);
+    let output = cmd
+        .args(&["--replay", &recording("get_missing_server")])
+        .args(&["get", "server", "missing"])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(4));
+    assert!(output.stdout.is_empty());
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["error"]["kind"], "http");
+    assert_eq!(error["error"]["status"], 404);
+    assert_eq!(error["error"]["request_id"], "req-missing");
+    assert_eq!(
+        error["error"]["message"],
+        "HTTP 404: Instance missing could not be found. (request id req-missing)"
+    );
+}
+
+#[test]
+fn list_servers_with_columns_filter_and_sort() {
+    let mut cmd = create_noauth_cmd(vec![("OS_COMPUTE_ENDPOINT_OVERRIDE", "https://compute.example.com/v2.1")]);
+    let output = cmd
+        .args(&["--replay", &recording("list_servers"), "-f", "csv"])
+        .args(&["list", "servers", "--columns", "name,id", "--filter", "name=server-2"])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "name,id\nserver-2,2\n\n");
+
+    let mut cmd = create_noauth_cmd(vec![("OS_COMPUTE_ENDPOINT_OVERRIDE", "https://compute.example.com/v2.1")]);
+    let output = cmd
+        .args(&["--replay", &recording("list_servers")])
+        .args(&["list", "servers", "--columns", "id", "--sort-by", "name", "--reverse"])
+        .output()
+        .unwrap();
+    let servers: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        servers,
+        json!({"servers": [{"id": "5"}, {"id": "4"}, {"id": "3"}, {"id": "2"}, {"id": "1"}]})
+    );
+}
+
+#[test]
+fn sorting_merges_streamed_pages() {
+    let list_servers = |extra: &[&str]| {
+        let mut cmd = create_noauth_cmd(vec![("OS_COMPUTE_ENDPOINT_OVERRIDE", "https://compute.example.com/v2.1")]);
+        let output = cmd
+            .args(&["--replay", &recording("list_servers_paged"), "-f", "value", "--columns", "id"])
+            .args(&["list", "--page-size", "2", "servers"])
+            .args(extra)
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+    // every page is printed as soon as it arrives
+    assert_eq!(list_servers(&[]), "1\n2\n3\n4\n5\n");
+    assert_eq!(list_servers(&["--sort-by", "name", "--reverse"]), "5\n4\n3\n2\n1\n");
+    assert_eq!(list_servers(&["--query", "length(servers)"]), "5\n");
+    assert_eq!(list_servers(&["--query", "max_by(servers, &name).id"]), "5\n");
+}
+
+#[test]
+fn list_servers_with_query() {
+    let mut cmd = create_noauth_cmd(vec![("OS_COMPUTE_ENDPOINT_OVERRIDE", "https://compute.example.com/v2.1")]);
+    let output = cmd
+        .args(&["--replay", &recording("list_servers")])
+        .args(&["list", "servers", "--query", "servers[?to_number(id) > `3`].{ID: id, Name: name} | [?Name != 'server-5']"])
+        .output()
+        .unwrap();
+    let servers: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(servers, json!([{"ID": "4", "Name": "server-4"}]));
+
+    let mut cmd = create_noauth_cmd(vec![("OS_COMPUTE_ENDPOINT_OVERRIDE", "https://compute.example.com/v2.1")]);
+    let output = cmd
+        .args(&["--replay", &recording("list_servers"), "-f", "csv"])
+        .args(&["list", "servers", "--query", "servers[-2:].{id: id, name: name}"])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "id,name\n4,server-4\n5,server-5\n\n");
+
+    let mut cmd = create_noauth_cmd(vec![("OS_COMPUTE_ENDPOINT_OVERRIDE", "https://compute.example.com/v2.1")]);
+    let output = cmd
+        .args(&["--replay", &recording("list_servers")])
+        .args(&["list", "servers", "--query", "servers[?name=server-1]"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("use '==' to compare values"));
+}
+
+#[test]
+fn list_servers_as_value_ndjson_and_shell() {
+    let list_servers = |format: &str, extra: &[&str]| {
+        let mut cmd = create_noauth_cmd(vec![("OS_COMPUTE_ENDPOINT_OVERRIDE", "https://compute.example.com/v2.1")]);
+        let output = cmd
+            .args(&["--replay", &recording("list_servers"), "-f", format])
+            .args(&["list", "servers"])
+            .args(extra)
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(list_servers("value", &["--query", "servers[:2].name"]), "server-1\nserver-2\n");
+    assert_eq!(
+        list_servers("ndjson", &["--filter", "id=5"]),
+        "{\"id\":\"5\",\"name\":\"server-5\"}\n"
+    );
+    assert_eq!(
+        list_servers("shell", &["--query", "servers[0]"]),
+        "os_id=\"1\"\nos_name=\"server-1\"\n"
+    );
+    assert_eq!(
+        list_servers("shell", &["--query", "servers[0]", "--prefix", "server_"]),
+        "server_id=\"1\"\nserver_name=\"server-1\"\n"
+    );
+    assert_eq!(list_servers("shell", &["--query", "servers[0]", "--prefix", "$(x)"]), "");
+    let yaml: serde_json::Value = serde_yaml::from_str(&list_servers("yaml", &["--columns", "id"])).unwrap();
+    assert_eq!(yaml, json!({"servers": [{"id": "1"}, {"id": "2"}, {"id": "3"}, {"id": "4"}, {"id": "5"}]}));
+}
+
+#[test]
+fn cache_output_applies_query() {
+    let mut cmd = create_noauth_cmd(vec![]);
+    let output = cmd.args(&["-f", "value", "cache", "--query", "type(cache)"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "array\n");
+}
@@ -53,7 +53,7 @@ impl From<Vec<&str>> for Output {
     }
 }
 
-pub fn create_cmd() -> Command {
+fn program_path() -> std::path::PathBuf {
     let root = env::current_exe()
         .unwrap()
         .parent()
@@ -68,6 +68,50 @@ pub fn create_cmd() -> Command {
     } else {
         program = root.join("openstack-client");
     }
+    program
+}
+
+fn isolated_dir() -> std::path::PathBuf {
+    // stands in for the home directory, so a developer's clouds.yaml, secure.yaml or token cache is never used
+    let mut dir = env::temp_dir();
+    dir.push("openstack-client-tests");
+    dir.push("home");
+    std::fs::create_dir_all(dir.join("cache")).expect("unable to create home dir");
+    for (name, content) in &[("clouds.yaml", "clouds: {}\n"), ("secure.yaml", "{}\n")] {
+        if !dir.join(name).is_file() {
+            std::fs::write(dir.join(name), content).expect("unable to create config file");
+        }
+    }
+    dir
+}
+
+fn isolate(cmd: &mut Command) {
+    let home = isolated_dir();
+    let path = |x: &str| home.join(x).to_string_lossy().to_string();
+    cmd.current_dir(&home)
+        .env("HOME", &home)
+        .env("XDG_CONFIG_HOME", path("config"))
+        .env("XDG_CACHE_HOME", path("cache"))
+        .env("OS_CLIENT_CONFIG_FILE", path("clouds.yaml"))
+        .env("OS_CLIENT_SECURE_FILE", path("secure.yaml"))
+        .env("OS_CLIENT_CACHE_DIR", path("cache"));
+}
+
+pub fn create_noauth_cmd(overrides: Vec<(&str, &str)>) -> Command {
+    // no keystone and no token cache, every endpoint comes from an override
+    let filtered_env: HashMap<String, String> = env::vars()
+        .filter(|&(ref k, _)| !k.starts_with("OS_") && !k.starts_with("XDG_"))
+        .collect();
+
+    let mut cmd = process::Command::new(program_path());
+    cmd.env_clear().envs(filtered_env);
+    isolate(&mut cmd);
+    cmd.env("OS_AUTH_TYPE", "none").envs(overrides);
+    cmd
+}
+
+pub fn create_cmd() -> Command {
+    let program = program_path();
 
     let env = vec![
         ("OS_USERNAME", "username"),
@@ -76,11 +120,14 @@ pub fn create_cmd() -> Command {
     ];
 
     let filtered_env: HashMap<String, String> = env::vars()
-        .filter(|&(ref k, _)| !k.starts_with("OS_"))
+        .filter(|&(ref k, _)| !k.starts_with("OS_") && !k.starts_with("XDG_"))
         .collect();
 
     let mut dir = env::temp_dir();
-    let auth_cache_name = "openstack-client-E78E50ECD12BFBAA";
+    dir.push("openstack-client-tests");
+    std::fs::create_dir_all(&dir).expect("unable to create cache dir");
+    let cache_dir = dir.to_string_lossy().to_string();
+    let auth_cache_name = "365E5DF5E79EDC41.cache";
     dir.push(auth_cache_name);
 
     let mut file = std::fs::File::create(dir).expect("unable to create file");
@@ -105,8 +152,7 @@ pub fn create_cmd() -> Command {
                 "project_domain_id": "1234",
                 "project_domain_name": "domain",
                 "trust_id": ""
-            },
-            "only_use_public_endpoints": true
+            }
         },
         "token": "token",
         "token_expiry": "2270-06-02T21:16:34.000000Z",
@@ -133,14 +179,20 @@ pub fn create_cmd() -> Command {
     file.write(format!("{}", cache).as_bytes()).unwrap();
 
     let mut cmd = process::Command::new(program);
-    cmd.env_clear()
-        .envs(filtered_env)
-        .envs(env)
+    cmd.env_clear().envs(filtered_env);
+    isolate(&mut cmd);
+    cmd.envs(env)
+        .env("OS_CLIENT_CACHE_DIR", cache_dir)
         // .arg("-vvv")
         .arg("--use-cache");
     cmd
 }
 
+pub fn recording(name: &str) -> String {
+    // recorded with --record, the responses are served with --replay
+    format!("{}/tests/recordings/{}.jsonl", env!("CARGO_MANIFEST_DIR"), name)
+}
+
 pub fn make_args(mut args: Vec<&'static str>) -> Vec<&str> {
     args.insert(1, "--dry-run");
     args